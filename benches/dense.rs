@@ -0,0 +1,48 @@
+//! Compare the matrix product backends on MNIST sized dense layers:
+//!
+//! cargo bench --bench dense
+//! cargo bench --bench dense --features openblas
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use jabba::{
+    activation::ActivationType,
+    nn::{NNBuilder, NNOptions, StopCondition},
+    Matrix,
+};
+
+const BATCH_SIZE: usize = 120;
+
+fn options() -> NNOptions {
+    NNOptions {
+        log_interval: None,
+        log_batches: false,
+        test: false,
+        batch_size: BATCH_SIZE,
+        stop_condition: StopCondition::Epoch(0),
+        ..Default::default()
+    }
+}
+
+fn dense(c: &mut Criterion) {
+    let x = Matrix::new_random(784, BATCH_SIZE);
+    let y = Matrix::new_random(10, BATCH_SIZE);
+
+    let mut nn = NNBuilder::new(784)
+        .options(options())
+        .add_layer(200, ActivationType::ReLuLeaky)
+        .add_layer(200, ActivationType::ReLuLeaky)
+        .add_layer(10, ActivationType::ReLuLeaky)
+        .build();
+
+    c.bench_function("feed forward 784-200-200-10", |b| {
+        b.iter(|| black_box(nn.feed_forward(&x)))
+    });
+
+    // a single epoch of one batch, forward and backward
+    c.bench_function("train batch 784-200-200-10", |b| {
+        b.iter(|| nn.train(&x, &y, &x, &y))
+    });
+}
+
+criterion_group!(benches, dense);
+criterion_main!(benches);
@@ -1,24 +1,22 @@
-use std::fs;
-
 use jabba::{
     activation::ActivationType,
+    data::read_idx,
     nn::{NNBuilder, NNOptions, StopCondition},
     optimizers::OptimizerType,
     storage,
     utils::one_hot,
-    Matrix,
 };
 
 fn main() {
-    let y_train = read_labels_file("examples/mnist/train-labels.idx1-ubyte");
-    let x_train = read_images_file("examples/mnist/train-images.idx3-ubyte");
+    let y_train = read_idx("examples/mnist/train-labels.idx1-ubyte", false).unwrap();
+    let x_train = read_idx("examples/mnist/train-images.idx3-ubyte", true).unwrap();
 
-    let y_train = one_hot(&y_train);
+    let y_train = one_hot(&y_train, 10);
 
-    let y_test = read_labels_file("examples/mnist/t10k-labels.idx1-ubyte");
-    let x_test = read_images_file("examples/mnist/t10k-images.idx3-ubyte");
+    let y_test = read_idx("examples/mnist/t10k-labels.idx1-ubyte", false).unwrap();
+    let x_test = read_idx("examples/mnist/t10k-images.idx3-ubyte", true).unwrap();
 
-    let y_test = one_hot(&y_test);
+    let y_test = one_hot(&y_test, 10);
 
     let options = NNOptions {
         log_interval: Some(1),
@@ -44,40 +42,3 @@ fn main() {
 
     storage::write_to("examples/mnist-nn.txt", &nn).unwrap()
 }
-
-fn read_labels_file(path: &str) -> Matrix {
-    let bytes = fs::read(path).unwrap();
-    let magic = to_u32(&bytes[0..4]);
-
-    assert_eq!(magic, 2049);
-
-    let num_items = to_u32(&bytes[4..8]) as usize;
-    let m = Matrix::from_iterator(1, num_items, bytes[8..].iter().map(|&x| x as f32));
-
-    m
-}
-
-fn read_images_file(path: &str) -> Matrix {
-    let bytes = fs::read(path).unwrap();
-    let magic = to_u32(&bytes[0..4]);
-
-    assert_eq!(magic, 2051);
-
-    let num_images = to_u32(&bytes[4..8]) as usize;
-    let nrows = to_u32(&bytes[8..12]) as usize;
-    let ncols = to_u32(&bytes[12..16]) as usize;
-
-    assert_eq!(bytes.len(), 16 + num_images * nrows * ncols);
-
-    let m = Matrix::from_iterator(
-        nrows * ncols,
-        num_images,
-        bytes[16..].iter().map(|&x| x as f32 / 255.),
-    );
-
-    m
-}
-
-fn to_u32(bytes: &[u8]) -> u32 {
-    u32::from_be_bytes(bytes.try_into().unwrap())
-}
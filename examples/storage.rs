@@ -4,7 +4,7 @@ use image::{imageops::FilterType, open};
 use jabba::{storage::read_from, Matrix};
 
 fn main() {
-    let path = env::args().last().unwrap();
+    let path = env::args().next_back().unwrap();
 
     let img = open(path)
         .unwrap()
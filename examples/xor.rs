@@ -21,7 +21,7 @@ fn main() {
         log_interval: Some(5000),
         batch_size: 2,
         learning_rate: 0.01,
-        stop_condition: StopCondition::Loss(0.002),
+        stop_condition: StopCondition::Loss(0.001),
         ..Default::default()
     };
 
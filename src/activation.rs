@@ -2,7 +2,7 @@ use std::str::FromStr;
 
 use crate::Matrix;
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug)]
 pub enum ActivationType {
     ReLu,
     ReLuLeaky,
@@ -32,6 +32,7 @@ impl ActivationType {
     }
 }
 
+#[derive(Clone)]
 pub(crate) struct Activation {
     pub(crate) activation_type: ActivationType,
 
@@ -0,0 +1,289 @@
+use std::sync::Mutex;
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rand_distr::{Distribution, Normal};
+
+use crate::{layers::Shape, Matrix};
+
+/// A random change to a single image, see [Augmentation]
+pub trait Transform: Send + Sync {
+    /// `image` holds the pixels of one sample in the layout of `shape`
+    fn apply(&self, image: &mut [f32], shape: Shape, rng: &mut StdRng);
+}
+
+/// Transforms every image of a batch on the fly, in the order the transforms
+/// were added and with new random values for every image. Set it on a
+/// [DataLoader](crate::data::DataLoader) to augment the training data
+pub struct Augmentation {
+    shape: Shape,
+    transforms: Vec<Box<dyn Transform>>,
+    rng: Mutex<StdRng>,
+}
+
+impl Augmentation {
+    /// The same seed gives the same images for the same batches
+    pub fn new(shape: Shape, seed: u64) -> Self {
+        Augmentation {
+            shape,
+            transforms: vec![],
+            rng: Mutex::new(StdRng::seed_from_u64(seed)),
+        }
+    }
+
+    pub fn then<T: Transform + 'static>(mut self, transform: T) -> Self {
+        self.transforms.push(Box::new(transform));
+        self
+    }
+
+    /// Transform every column of `x`
+    pub fn apply(&self, x: &mut Matrix) {
+        assert_eq!(x.nrows(), self.shape.len(), "wrong image shape");
+        let mut rng = self.rng.lock().unwrap();
+
+        for mut image in x.column_iter_mut() {
+            for transform in &self.transforms {
+                transform.apply(image.as_mut_slice(), self.shape, &mut rng);
+            }
+        }
+    }
+}
+
+/// Moves the image by up to `max` pixels in both directions, pixels moved in
+/// from outside are zero
+pub struct Shift {
+    pub max: usize,
+}
+
+/// Rotates the image around its center by up to `max_degrees` either way
+pub struct Rotate {
+    pub max_degrees: f32,
+}
+
+/// Zooms in or out around the center by a factor between `min` and `max`
+pub struct Scale {
+    pub min: f32,
+    pub max: f32,
+}
+
+/// Moves every pixel along a random displacement field, smoothed with a
+/// gaussian of width `sigma` and scaled by `alpha`, as in Simard et al. 2003
+pub struct ElasticDistortion {
+    alpha: f32,
+    sigma: f32,
+}
+
+impl ElasticDistortion {
+    pub fn new(alpha: f32, sigma: f32) -> Self {
+        assert!(sigma > 0., "the smoothing width must be positive");
+        ElasticDistortion { alpha, sigma }
+    }
+}
+
+/// Adds gaussian noise to every pixel
+pub struct Noise {
+    distr: Normal<f32>,
+}
+
+impl Noise {
+    /// `std` is the standard deviation of the noise
+    pub fn new(std: f32) -> Self {
+        assert!(
+            std >= 0. && std.is_finite(),
+            "the standard deviation must be finite and >= 0"
+        );
+        Noise {
+            distr: Normal::new(0., std).unwrap(),
+        }
+    }
+}
+
+impl Transform for Shift {
+    fn apply(&self, image: &mut [f32], shape: Shape, rng: &mut StdRng) {
+        let max = self.max as i64;
+        let dx = rng.gen_range(-max..=max) as f32;
+        let dy = rng.gen_range(-max..=max) as f32;
+
+        warp(image, shape, |y, x| (y - dy, x - dx));
+    }
+}
+
+impl Transform for Rotate {
+    fn apply(&self, image: &mut [f32], shape: Shape, rng: &mut StdRng) {
+        let angle = rng
+            .gen_range(-self.max_degrees..=self.max_degrees)
+            .to_radians();
+        let (sin, cos) = angle.sin_cos();
+        let (cy, cx) = center(shape);
+
+        // the inverse rotation finds where every pixel came from
+        warp(image, shape, |y, x| {
+            let (y, x) = (y - cy, x - cx);
+            (cy - sin * x + cos * y, cx + cos * x + sin * y)
+        });
+    }
+}
+
+impl Transform for Scale {
+    fn apply(&self, image: &mut [f32], shape: Shape, rng: &mut StdRng) {
+        let factor = rng.gen_range(self.min..=self.max);
+        let (cy, cx) = center(shape);
+
+        warp(image, shape, |y, x| {
+            (cy + (y - cy) / factor, cx + (x - cx) / factor)
+        });
+    }
+}
+
+impl Transform for ElasticDistortion {
+    fn apply(&self, image: &mut [f32], shape: Shape, rng: &mut StdRng) {
+        let (h, w) = (shape.height, shape.width);
+        let mut field = || {
+            let mut field = (0..h * w)
+                .map(|_| rng.gen_range(-1.0..=1.0))
+                .collect::<Vec<f32>>();
+            blur(&mut field, h, w, self.sigma);
+            field
+        };
+        let (dy, dx) = (field(), field());
+
+        warp(image, shape, |y, x| {
+            let i = y as usize * w + x as usize;
+            (y + self.alpha * dy[i], x + self.alpha * dx[i])
+        });
+    }
+}
+
+impl Transform for Noise {
+    fn apply(&self, image: &mut [f32], _shape: Shape, rng: &mut StdRng) {
+        for pixel in image {
+            *pixel += self.distr.sample(rng);
+        }
+    }
+}
+
+fn center(shape: Shape) -> (f32, f32) {
+    (
+        (shape.height as f32 - 1.) / 2.,
+        (shape.width as f32 - 1.) / 2.,
+    )
+}
+
+/// Replace every pixel by the bilinearly interpolated value at the position
+/// `source` returns for its row and column, in every channel
+fn warp(image: &mut [f32], shape: Shape, source: impl Fn(f32, f32) -> (f32, f32)) {
+    let original = image.to_vec();
+
+    for y in 0..shape.height {
+        for x in 0..shape.width {
+            let (sy, sx) = source(y as f32, x as f32);
+
+            for c in 0..shape.channels {
+                image[shape.index(c, y, x)] = sample(&original, shape, c, sy, sx);
+            }
+        }
+    }
+}
+
+/// Zero outside of the image
+fn sample(image: &[f32], shape: Shape, c: usize, y: f32, x: f32) -> f32 {
+    let (y0, x0) = (y.floor(), x.floor());
+    let (fy, fx) = (y - y0, x - x0);
+
+    let pixel = |y: f32, x: f32| {
+        if y < 0. || x < 0. || y >= shape.height as f32 || x >= shape.width as f32 {
+            0.
+        } else {
+            image[shape.index(c, y as usize, x as usize)]
+        }
+    };
+
+    let top = pixel(y0, x0) * (1. - fx) + pixel(y0, x0 + 1.) * fx;
+    let bottom = pixel(y0 + 1., x0) * (1. - fx) + pixel(y0 + 1., x0 + 1.) * fx;
+
+    top * (1. - fy) + bottom * fy
+}
+
+/// Separable gaussian blur of a single channel, the border is repeated
+fn blur(field: &mut [f32], h: usize, w: usize, sigma: f32) {
+    let radius = (3. * sigma).ceil() as i64;
+    let kernel = (-radius..=radius)
+        .map(|i| (-(i * i) as f32 / (2. * sigma * sigma)).exp())
+        .collect::<Vec<_>>();
+    let total = kernel.iter().sum::<f32>();
+
+    let mut pass = |step: usize, len: usize, lines: usize, stride: usize| {
+        let original = field.to_vec();
+
+        for line in 0..lines {
+            for i in 0..len {
+                let value = kernel
+                    .iter()
+                    .enumerate()
+                    .map(|(k, weight)| {
+                        let j = (i as i64 + k as i64 - radius).clamp(0, len as i64 - 1);
+                        weight * original[line * stride + j as usize * step]
+                    })
+                    .sum::<f32>();
+
+                field[line * stride + i * step] = value / total;
+            }
+        }
+    };
+
+    // along the rows, then along the columns
+    pass(1, w, h, w);
+    pass(w, h, w, 1);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        data::{Augmentation, ElasticDistortion, Noise, Rotate, Scale, Shift},
+        layers::Shape,
+        Matrix,
+    };
+
+    #[test]
+    fn test_augmentation() {
+        let shape = Shape::new(1, 5, 5);
+        let mut x = Matrix::zeros(shape.len(), 20);
+        x.row_mut(shape.index(0, 2, 2)).fill(1.);
+
+        // whole pixel shifts move the dot without blurring it
+        let mut shifted = x.clone();
+        Augmentation::new(shape, 0)
+            .then(Shift { max: 1 })
+            .apply(&mut shifted);
+        for image in shifted.column_iter() {
+            assert_eq!(image.sum(), 1.);
+            assert_eq!(image.max(), 1.);
+            let at = image.argmax().0;
+            assert!((1..=3).contains(&(at / 5)) && (1..=3).contains(&(at % 5)));
+        }
+
+        let augment = |seed| {
+            let mut x = x.clone();
+            Augmentation::new(shape, seed)
+                .then(Rotate { max_degrees: 15. })
+                .then(Scale { min: 0.9, max: 1.1 })
+                .then(ElasticDistortion::new(1., 1.))
+                .then(Noise::new(0.01))
+                .apply(&mut x);
+            x
+        };
+        assert_eq!(augment(1), augment(1));
+        assert_ne!(augment(1), augment(2));
+    }
+
+    #[test]
+    #[should_panic(expected = "the smoothing width must be positive")]
+    fn test_elastic_distortion_sigma() {
+        ElasticDistortion::new(1., 0.);
+    }
+
+    #[test]
+    #[should_panic(expected = "the standard deviation must be finite")]
+    fn test_noise_std() {
+        Noise::new(-1.);
+    }
+}
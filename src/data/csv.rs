@@ -0,0 +1,318 @@
+use std::{
+    fs,
+    io::{Error, ErrorKind},
+    path::Path,
+};
+
+use crate::{
+    utils::{one_hot, LabelEncoder},
+    Matrix,
+};
+
+/// Refers to a column of a CSV file by its header or by its position
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Column {
+    Name(String),
+    Index(usize),
+}
+
+/// What to do with empty fields and fields that read `NA` or `NaN`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MissingValues {
+    /// fail with an error
+    #[default]
+    Error,
+    /// leave out every row with a missing value
+    DropRow,
+    Zero,
+    /// the mean of the column's values, not supported for categorical labels
+    Mean,
+}
+
+pub struct CsvOptions {
+    pub delimiter: char,
+    /// whether the first line holds the names of the columns
+    pub has_header: bool,
+    pub label_columns: Vec<Column>,
+    /// defaults to all columns that are not labels
+    pub feature_columns: Option<Vec<Column>>,
+    /// one-hot encode numeric labels as well, categorical labels (columns
+    /// that are not all numbers) are always one-hot encoded
+    pub one_hot: bool,
+    pub missing_values: MissingValues,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        CsvOptions {
+            delimiter: ',',
+            has_header: true,
+            label_columns: vec![],
+            feature_columns: None,
+            one_hot: false,
+            missing_values: MissingValues::default(),
+        }
+    }
+}
+
+/// Features and labels read from a CSV file, one column per row of the file
+pub struct CsvData {
+    pub x: Matrix,
+    /// the labels of all label columns, stacked in the order they were selected
+    pub y: Matrix,
+    pub feature_names: Vec<String>,
+    pub label_names: Vec<String>,
+    /// for every label column, the class of every one-hot row, empty for
+    /// numeric labels that were not one-hot encoded
+    pub classes: Vec<Vec<String>>,
+}
+
+pub fn read_csv<P: AsRef<Path>>(path: P, options: &CsvOptions) -> Result<CsvData, Error> {
+    let contents = fs::read_to_string(path)?;
+
+    parse_csv(&contents, options)
+}
+
+/// Like [read_csv] for a file that is already in memory. Quoted fields may
+/// contain the delimiter, but not line breaks
+pub fn parse_csv(contents: &str, options: &CsvOptions) -> Result<CsvData, Error> {
+    let mut lines = contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty());
+
+    let header = if options.has_header {
+        let (_, line) = lines.next().ok_or_else(|| invalid("empty CSV file"))?;
+        Some(split_fields(line, options.delimiter))
+    } else {
+        None
+    };
+
+    let rows = lines
+        .map(|(i, line)| (i + 1, split_fields(line, options.delimiter)))
+        .collect::<Vec<_>>();
+    let num_columns = match (&header, rows.first()) {
+        (Some(header), _) => header.len(),
+        (None, Some((_, row))) => row.len(),
+        (None, None) => return Err(invalid("empty CSV file")),
+    };
+
+    if let Some((line, row)) = rows.iter().find(|(_, row)| row.len() != num_columns) {
+        return Err(invalid(&format!(
+            "line {line} has {} fields instead of {num_columns}",
+            row.len()
+        )));
+    }
+
+    let name = |c: usize| match &header {
+        Some(header) => header[c].clone(),
+        None => c.to_string(),
+    };
+    let resolve = |column: &Column| match column {
+        Column::Index(c) if *c < num_columns => Ok(*c),
+        Column::Name(n) => header
+            .as_ref()
+            .and_then(|header| header.iter().position(|h| h == n))
+            .ok_or_else(|| invalid(&format!("no column named {n}"))),
+        Column::Index(c) => Err(invalid(&format!("no column {c}"))),
+    };
+
+    let labels = options
+        .label_columns
+        .iter()
+        .map(resolve)
+        .collect::<Result<Vec<_>, _>>()?;
+    let features = match &options.feature_columns {
+        Some(columns) => columns.iter().map(resolve).collect::<Result<Vec<_>, _>>()?,
+        None => (0..num_columns).filter(|c| !labels.contains(c)).collect(),
+    };
+
+    let rows = match options.missing_values {
+        MissingValues::DropRow => rows
+            .into_iter()
+            .filter(|(_, row)| !features.iter().chain(&labels).any(|&c| is_missing(&row[c])))
+            .collect(),
+        _ => rows,
+    };
+
+    let mut x = Matrix::zeros(features.len(), rows.len());
+    for (r, &c) in features.iter().enumerate() {
+        if is_categorical(&rows, c) {
+            return Err(invalid(&format!("feature {} is not numeric", name(c))));
+        }
+
+        let values = numeric_column(&rows, c, options.missing_values, &name(c))?;
+        x.row_mut(r).copy_from_slice(&values);
+    }
+
+    let mut label_rows = vec![];
+    let mut classes = vec![];
+    for &c in &labels {
+        if is_categorical(&rows, c) {
+            let encoder = categorical_column(&rows, c, &name(c))?;
+            let labels = rows.iter().map(|(_, row)| &row[c]).collect::<Vec<_>>();
+            label_rows.push(encoder.one_hot(&labels));
+            classes.push(encoder.classes().to_vec());
+        } else {
+            let values = numeric_column(&rows, c, options.missing_values, &name(c))?;
+
+            if options.one_hot {
+                if let Some(v) = values.iter().find(|&&v| v < 0. || v.fract() != 0.) {
+                    return Err(invalid(&format!(
+                        "label {v} in {} is not a class index",
+                        name(c)
+                    )));
+                }
+
+                let num_classes = values.iter().fold(0., |a: f32, &b| a.max(b)) as usize + 1;
+                let indices = Matrix::from_row_slice(1, values.len(), &values);
+                label_rows.push(one_hot(&indices, num_classes));
+                classes.push((0..num_classes).map(|i| i.to_string()).collect());
+            } else {
+                label_rows.push(Matrix::from_row_slice(1, values.len(), &values));
+                classes.push(vec![]);
+            }
+        }
+    }
+
+    let num_label_rows = label_rows.iter().map(|m| m.nrows()).sum();
+    let mut y = Matrix::zeros(num_label_rows, rows.len());
+    let mut offset = 0;
+    for m in label_rows {
+        y.rows_mut(offset, m.nrows()).copy_from(&m);
+        offset += m.nrows();
+    }
+
+    Ok(CsvData {
+        x,
+        y,
+        feature_names: features.iter().map(|&c| name(c)).collect(),
+        label_names: labels.iter().map(|&c| name(c)).collect(),
+        classes,
+    })
+}
+
+fn numeric_column(
+    rows: &[(usize, Vec<String>)],
+    c: usize,
+    missing_values: MissingValues,
+    name: &str,
+) -> Result<Vec<f32>, Error> {
+    let values = rows
+        .iter()
+        .map(|(_, row)| row[c].parse::<f32>().ok().filter(|_| !is_missing(&row[c])))
+        .collect::<Vec<_>>();
+
+    let present = values.iter().flatten().collect::<Vec<_>>();
+    let fill = match missing_values {
+        MissingValues::Zero => 0.,
+        MissingValues::Mean if !present.is_empty() => {
+            present.iter().copied().sum::<f32>() / present.len() as f32
+        }
+        _ => f32::NAN,
+    };
+
+    values
+        .iter()
+        .zip(rows)
+        .map(|(v, (line, _))| match v {
+            Some(v) => Ok(*v),
+            None if !fill.is_nan() => Ok(fill),
+            None => Err(invalid(&format!("missing value for {name} on line {line}"))),
+        })
+        .collect()
+}
+
+/// The classes of a categorical column, sorted so that the encoding does
+/// not depend on the order of the rows
+fn categorical_column(
+    rows: &[(usize, Vec<String>)],
+    c: usize,
+    name: &str,
+) -> Result<LabelEncoder, Error> {
+    if let Some((line, _)) = rows.iter().find(|(_, row)| is_missing(&row[c])) {
+        return Err(invalid(&format!("missing label for {name} on line {line}")));
+    }
+
+    let labels = rows.iter().map(|(_, row)| &row[c]).collect::<Vec<_>>();
+
+    Ok(LabelEncoder::fit(&labels))
+}
+
+/// Categorical columns hold values that are not numbers
+fn is_categorical(rows: &[(usize, Vec<String>)], c: usize) -> bool {
+    rows.iter()
+        .any(|(_, row)| !is_missing(&row[c]) && row[c].parse::<f32>().is_err())
+}
+
+fn is_missing(field: &str) -> bool {
+    matches!(field, "" | "NA" | "NaN" | "nan")
+}
+
+fn split_fields(line: &str, delimiter: char) -> Vec<String> {
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            c if c == delimiter && !quoted => {
+                fields.push(field.trim().to_string());
+                field.clear();
+            }
+            c => field.push(c),
+        }
+    }
+    fields.push(field.trim().to_string());
+
+    fields
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data::{parse_csv, Column, CsvOptions, MissingValues};
+    use nalgebra::dmatrix;
+
+    #[test]
+    fn test_parse_csv() {
+        let contents = "\
+length,\"width, in cm\",species
+1.5,0.5,setosa
+2.0,,virginica
+3.0,1.5,setosa
+";
+        let options = CsvOptions {
+            label_columns: vec![Column::Name("species".to_string())],
+            missing_values: MissingValues::Mean,
+            ..Default::default()
+        };
+
+        let data = parse_csv(contents, &options).unwrap();
+        assert_eq!(data.x, dmatrix![1.5, 2., 3.; 0.5, 1., 1.5]);
+        assert_eq!(data.y, dmatrix![1., 0., 1.; 0., 1., 0.]);
+        assert_eq!(data.feature_names, vec!["length", "width, in cm"]);
+        assert_eq!(data.classes, vec![vec!["setosa", "virginica"]]);
+
+        let options = CsvOptions {
+            label_columns: vec![Column::Index(0)],
+            missing_values: MissingValues::DropRow,
+            one_hot: true,
+            ..Default::default()
+        };
+        let data = parse_csv("a,b\n1,NA\n0,4\n2,5\n", &options).unwrap();
+        assert_eq!(data.x, dmatrix![4., 5.]);
+        assert_eq!(data.y, dmatrix![1., 0.; 0., 0.; 0., 1.]);
+
+        assert!(parse_csv("a,b\n1,\n", &CsvOptions::default()).is_err());
+    }
+}
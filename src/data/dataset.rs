@@ -0,0 +1,119 @@
+use crate::Matrix;
+
+/// Inputs and labels of some samples, one column per sample and one label
+/// matrix per head of the network
+pub struct Batch {
+    pub x: Matrix,
+    pub y: Vec<Matrix>,
+    /// how much every sample counts in the loss, as a single row, see
+    /// [ClassWeights](crate::loss::ClassWeights)
+    pub weights: Option<Matrix>,
+}
+
+/// A collection of samples that can be read in any order, so that it does
+/// not have to fit in memory as a whole
+pub trait Dataset: Sync {
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The samples at `indices`, in that order
+    fn get(&self, indices: &[usize]) -> Batch;
+}
+
+/// A dataset that is already in memory, with one column per sample
+pub struct MatrixDataset<'a> {
+    x: &'a Matrix,
+    y: &'a [Matrix],
+    weights: Option<&'a Matrix>,
+}
+
+impl<'a> MatrixDataset<'a> {
+    /// `y` holds the labels of every head
+    pub fn new(x: &'a Matrix, y: &'a [Matrix]) -> Self {
+        assert!(
+            y.iter().all(|y| y.ncols() == x.ncols()),
+            "every sample needs a label"
+        );
+
+        MatrixDataset {
+            x,
+            y,
+            weights: None,
+        }
+    }
+
+    /// Weigh the loss of every sample, a single row with one column per sample
+    pub fn weights(mut self, weights: &'a Matrix) -> Self {
+        assert_eq!(
+            weights.shape(),
+            (1, self.x.ncols()),
+            "one weight per sample"
+        );
+
+        self.weights = Some(weights);
+        self
+    }
+}
+
+impl Dataset for MatrixDataset<'_> {
+    fn len(&self) -> usize {
+        self.x.ncols()
+    }
+
+    fn get(&self, indices: &[usize]) -> Batch {
+        Batch {
+            x: self.x.select_columns(indices),
+            y: self.y.iter().map(|y| y.select_columns(indices)).collect(),
+            weights: self.weights.map(|w| w.select_columns(indices)),
+        }
+    }
+}
+
+/// Loads every sample on demand, eg. from disk. `load` returns the input
+/// and the labels of a single sample as columns
+pub struct LazyDataset<F> {
+    len: usize,
+    load: F,
+}
+
+impl<F> LazyDataset<F>
+where
+    F: Fn(usize) -> (Matrix, Vec<Matrix>) + Sync,
+{
+    pub fn new(len: usize, load: F) -> Self {
+        LazyDataset { len, load }
+    }
+}
+
+impl<F> Dataset for LazyDataset<F>
+where
+    F: Fn(usize) -> (Matrix, Vec<Matrix>) + Sync,
+{
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn get(&self, indices: &[usize]) -> Batch {
+        let samples = indices.iter().map(|&i| (self.load)(i)).collect::<Vec<_>>();
+
+        let columns = samples.iter().map(|(x, _)| x.column(0)).collect::<Vec<_>>();
+        let num_heads = samples.first().map_or(0, |(_, y)| y.len());
+
+        Batch {
+            x: Matrix::from_columns(&columns),
+            y: (0..num_heads)
+                .map(|h| {
+                    let columns = samples
+                        .iter()
+                        .map(|(_, y)| y[h].column(0))
+                        .collect::<Vec<_>>();
+                    Matrix::from_columns(&columns)
+                })
+                .collect(),
+            weights: None,
+        }
+    }
+}
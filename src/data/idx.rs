@@ -0,0 +1,155 @@
+use std::{
+    fs,
+    io::{Error, ErrorKind},
+    path::Path,
+};
+
+use crate::Matrix;
+
+/// Read an IDX file, as used by MNIST and Fashion-MNIST, into one column per
+/// entry of its first dimension. With `normalize` set, integer values are
+/// divided by the largest value of their type, so u8 pixels end up in [0, 1]
+pub fn read_idx<P: AsRef<Path>>(path: P, normalize: bool) -> Result<Matrix, Error> {
+    let bytes = fs::read(path)?;
+
+    parse_idx(&bytes, normalize).map(|(_, m)| m)
+}
+
+/// Like [read_idx] for a file that is already in memory, also returns the
+/// dimensions stored in the header
+pub fn parse_idx(bytes: &[u8], normalize: bool) -> Result<(Vec<usize>, Matrix), Error> {
+    if bytes.len() < 4 || bytes[0] != 0 || bytes[1] != 0 {
+        return Err(invalid("not an IDX file"));
+    }
+
+    let dtype = DType::from_code(bytes[2])?;
+    let num_dims = bytes[3] as usize;
+    if num_dims == 0 {
+        return Err(invalid("IDX file without dimensions"));
+    }
+
+    let header_len = 4 + 4 * num_dims;
+    let header = bytes
+        .get(4..header_len)
+        .ok_or_else(|| invalid("IDX header is truncated"))?;
+    let dims = header
+        .chunks_exact(4)
+        .map(|b| u32::from_be_bytes(b.try_into().unwrap()) as usize)
+        .collect::<Vec<_>>();
+
+    let num_samples = dims[0];
+    let sample_len = dims[1..]
+        .iter()
+        .try_fold(1usize, |acc, &d| acc.checked_mul(d))
+        .ok_or_else(|| invalid("IDX dimensions are too large"))?;
+    let data_len = num_samples
+        .checked_mul(sample_len)
+        .and_then(|len| len.checked_mul(dtype.size()))
+        .ok_or_else(|| invalid("IDX dimensions are too large"))?;
+
+    let data = &bytes[header_len..];
+    if data.len() != data_len {
+        return Err(invalid(&format!(
+            "expected {data_len} bytes of data, found {}",
+            data.len()
+        )));
+    }
+
+    let scale = if normalize { 1. / dtype.max() } else { 1. };
+    let values = data
+        .chunks_exact(dtype.size())
+        .map(|b| (dtype.read(b) * scale) as f32);
+
+    Ok((dims, Matrix::from_iterator(sample_len, num_samples, values)))
+}
+
+#[derive(Clone, Copy)]
+enum DType {
+    U8,
+    I8,
+    I16,
+    I32,
+    F32,
+    F64,
+}
+
+impl DType {
+    fn from_code(code: u8) -> Result<Self, Error> {
+        match code {
+            0x08 => Ok(DType::U8),
+            0x09 => Ok(DType::I8),
+            0x0B => Ok(DType::I16),
+            0x0C => Ok(DType::I32),
+            0x0D => Ok(DType::F32),
+            0x0E => Ok(DType::F64),
+            _ => Err(invalid(&format!("unknown IDX data type {code:#04x}"))),
+        }
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            DType::U8 | DType::I8 => 1,
+            DType::I16 => 2,
+            DType::I32 | DType::F32 => 4,
+            DType::F64 => 8,
+        }
+    }
+
+    /// Values are divided by this when normalizing
+    fn max(&self) -> f64 {
+        match self {
+            DType::U8 => u8::MAX as f64,
+            DType::I8 => i8::MAX as f64,
+            DType::I16 => i16::MAX as f64,
+            DType::I32 => i32::MAX as f64,
+            DType::F32 | DType::F64 => 1.,
+        }
+    }
+
+    /// Values are stored big endian
+    fn read(&self, b: &[u8]) -> f64 {
+        match self {
+            DType::U8 => b[0] as f64,
+            DType::I8 => b[0] as i8 as f64,
+            DType::I16 => i16::from_be_bytes(b.try_into().unwrap()) as f64,
+            DType::I32 => i32::from_be_bytes(b.try_into().unwrap()) as f64,
+            DType::F32 => f32::from_be_bytes(b.try_into().unwrap()) as f64,
+            DType::F64 => f64::from_be_bytes(b.try_into().unwrap()),
+        }
+    }
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data::parse_idx;
+    use nalgebra::dmatrix;
+
+    #[test]
+    fn test_parse_idx() {
+        // two 2x2 u8 images
+        let mut bytes = vec![0, 0, 0x08, 3, 0, 0, 0, 2, 0, 0, 0, 2, 0, 0, 0, 2];
+        bytes.extend([0, 51, 102, 255, 1, 2, 3, 4]);
+
+        let (dims, m) = parse_idx(&bytes, true).unwrap();
+        assert_eq!(dims, vec![2, 2, 2]);
+        assert_eq!(m.shape(), (4, 2));
+        assert_eq!(m[(1, 0)], 0.2);
+        assert_eq!(m[(3, 0)], 1.);
+
+        // three i16 labels
+        let mut bytes = vec![0, 0, 0x0B, 1, 0, 0, 0, 3];
+        bytes.extend([0, 7, 0xFF, 0xFE, 1, 0]);
+
+        let (_, m) = parse_idx(&bytes, false).unwrap();
+        assert_eq!(m, dmatrix![7., -2., 256.]);
+
+        // missing a value
+        bytes.pop();
+        assert!(parse_idx(&bytes, false).is_err());
+        assert!(parse_idx(&[0, 0, 0x0A, 1], false).is_err());
+    }
+}
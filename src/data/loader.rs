@@ -0,0 +1,164 @@
+use std::{
+    sync::{mpsc::sync_channel, Mutex},
+    thread,
+};
+
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+
+use crate::data::{Augmentation, Batch, Dataset};
+
+/// Splits a [Dataset] into batches, optionally shuffled every epoch and
+/// built ahead of time on a background thread
+pub struct DataLoader<D> {
+    dataset: D,
+    batch_size: usize,
+    shuffle: bool,
+    /// number of batches built ahead, zero builds them on the calling thread
+    prefetch: usize,
+    augmentation: Option<Augmentation>,
+    rng: Mutex<StdRng>,
+}
+
+impl<D: Dataset> DataLoader<D> {
+    pub fn new(dataset: D, batch_size: usize) -> Self {
+        assert!(batch_size > 0);
+
+        DataLoader {
+            dataset,
+            batch_size,
+            shuffle: false,
+            prefetch: 0,
+            augmentation: None,
+            rng: Mutex::new(StdRng::from_entropy()),
+        }
+    }
+
+    /// Visit the samples in a different random order every epoch
+    pub fn shuffle(mut self, shuffle: bool) -> Self {
+        self.shuffle = shuffle;
+        self
+    }
+
+    /// Shuffle in the same orders on every run, see [Augmentation::new] to
+    /// make the augmentation reproducible as well
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.rng = Mutex::new(StdRng::seed_from_u64(seed));
+        self
+    }
+
+    /// Build up to `num_batches` batches on a background thread while the
+    /// network trains on the current one
+    pub fn prefetch(mut self, num_batches: usize) -> Self {
+        self.prefetch = num_batches;
+        self
+    }
+
+    /// Randomly change the inputs of every batch, after prefetching is
+    /// enabled this happens on the background thread
+    pub fn augment(mut self, augmentation: Augmentation) -> Self {
+        self.augmentation = Some(augmentation);
+        self
+    }
+
+    pub fn dataset(&self) -> &D {
+        &self.dataset
+    }
+
+    pub fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+
+    /// Number of batches in an epoch, the last one may be smaller
+    pub fn num_batches(&self) -> usize {
+        self.dataset.len().div_ceil(self.batch_size)
+    }
+
+    /// Call `f` with every batch of one epoch
+    pub fn for_each_batch(&self, mut f: impl FnMut(Batch)) {
+        let mut indices = (0..self.dataset.len()).collect::<Vec<_>>();
+        if self.shuffle {
+            indices.shuffle(&mut *self.rng.lock().unwrap());
+        }
+
+        let chunks = indices.chunks(self.batch_size);
+
+        if self.prefetch == 0 {
+            chunks.for_each(|chunk| f(self.get(chunk)));
+            return;
+        }
+
+        thread::scope(|scope| {
+            let (sender, receiver) = sync_channel(self.prefetch);
+
+            scope.spawn(move || {
+                for chunk in chunks {
+                    // the receiver is gone if f panicked
+                    if sender.send(self.get(chunk)).is_err() {
+                        break;
+                    }
+                }
+            });
+
+            receiver.iter().for_each(f);
+        });
+    }
+
+    fn get(&self, indices: &[usize]) -> Batch {
+        let mut batch = self.dataset.get(indices);
+        if let Some(augmentation) = &self.augmentation {
+            augmentation.apply(&mut batch.x);
+        }
+
+        batch
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        data::{DataLoader, LazyDataset},
+        Matrix,
+    };
+
+    #[test]
+    fn test_data_loader() {
+        let dataset = LazyDataset::new(10, |i| {
+            (
+                Matrix::from_element(2, 1, i as f32),
+                vec![Matrix::from_element(1, 1, 2. * i as f32)],
+            )
+        });
+        let loader = DataLoader::new(dataset, 4).shuffle(true).prefetch(2);
+
+        let mut sizes = vec![];
+        let mut seen = vec![];
+
+        loader.for_each_batch(|batch| {
+            sizes.push(batch.x.ncols());
+            assert_eq!(batch.y[0], batch.x.row(0) * 2.);
+
+            seen.extend(batch.x.row(1).iter().map(|&x| x as usize));
+        });
+
+        seen.sort();
+        assert_eq!(sizes, vec![4, 4, 2]);
+        assert_eq!(seen, (0..10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_seeded_shuffle() {
+        let order = |seed| {
+            let dataset = LazyDataset::new(20, |i| (Matrix::from_element(1, 1, i as f32), vec![]));
+            let loader = DataLoader::new(dataset, 5).shuffle(true).seed(seed);
+
+            let mut seen = vec![];
+            for _ in 0..2 {
+                loader.for_each_batch(|batch| seen.extend(batch.x.iter().copied()));
+            }
+            seen
+        };
+
+        assert_eq!(order(3), order(3));
+        assert_ne!(order(3), order(4));
+    }
+}
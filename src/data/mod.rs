@@ -0,0 +1,18 @@
+pub use augment::{Augmentation, ElasticDistortion, Noise, Rotate, Scale, Shift, Transform};
+pub use csv::{parse_csv, read_csv, Column, CsvData, CsvOptions, MissingValues};
+pub use dataset::{Batch, Dataset, LazyDataset, MatrixDataset};
+pub use idx::{parse_idx, read_idx};
+pub use loader::DataLoader;
+pub use scaler::{MinMaxScaler, RobustScaler, Scaler, StandardScaler};
+pub use split::{
+    cross_validate, k_fold_indices, split_indices, train_val_test_split, CrossValidation,
+    FoldMetrics, Split, SplitOptions,
+};
+
+mod augment;
+mod csv;
+mod dataset;
+mod idx;
+mod loader;
+mod scaler;
+mod split;
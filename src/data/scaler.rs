@@ -0,0 +1,221 @@
+use crate::{
+    storage::{matrix_from_string, matrix_to_string},
+    Matrix,
+};
+
+/// Transforms every feature (row) of the inputs with values fitted on the
+/// training data. A scaler set with [NN::set_scaler](crate::nn::NN::set_scaler)
+/// is applied to everything the network is trained on or predicts, and is
+/// saved with it
+pub trait Scaler: Send + Sync {
+    fn transform(&self, x: &Matrix) -> Matrix;
+
+    fn inverse_transform(&self, x: &Matrix) -> Matrix;
+
+    /// Used to find the right loader, see [Scaler::save]
+    fn name(&self) -> &str;
+
+    /// Everything needed to rebuild the scaler, one line per item
+    fn save(&self) -> Vec<String>;
+}
+
+/// Scales every feature to zero mean and unit variance
+pub struct StandardScaler(Affine);
+
+/// Scales every feature to the range `[0, 1]` of the training data
+pub struct MinMaxScaler(Affine);
+
+/// Centers every feature on its median and scales it by its interquartile
+/// range, so outliers have less influence than with a [StandardScaler]
+pub struct RobustScaler(Affine);
+
+impl StandardScaler {
+    pub fn fit(x: &Matrix) -> Self {
+        let n = x.ncols() as f32;
+        let mean = Matrix::from_column_slice(x.nrows(), 1, x.column_mean().as_slice());
+        let std = Matrix::from_fn(x.nrows(), 1, |i, _| {
+            (x.row(i).map(|v| (v - mean[i]).powi(2)).sum() / n).sqrt()
+        });
+
+        StandardScaler(Affine::new(mean, std))
+    }
+
+    /// Inverse of [Scaler::save]
+    pub fn load(lines: &[&str]) -> Self {
+        StandardScaler(Affine::load(lines))
+    }
+}
+
+impl MinMaxScaler {
+    pub fn fit(x: &Matrix) -> Self {
+        let min = Matrix::from_fn(x.nrows(), 1, |i, _| x.row(i).min());
+        let range = Matrix::from_fn(x.nrows(), 1, |i, _| x.row(i).max() - min[i]);
+
+        MinMaxScaler(Affine::new(min, range))
+    }
+
+    /// Inverse of [Scaler::save]
+    pub fn load(lines: &[&str]) -> Self {
+        MinMaxScaler(Affine::load(lines))
+    }
+}
+
+impl RobustScaler {
+    pub fn fit(x: &Matrix) -> Self {
+        let mut median = Matrix::zeros(x.nrows(), 1);
+        let mut iqr = Matrix::zeros(x.nrows(), 1);
+
+        for (i, row) in x.row_iter().enumerate() {
+            let mut values = row.iter().copied().collect::<Vec<_>>();
+            values.sort_by(f32::total_cmp);
+
+            median[i] = quantile(&values, 0.5);
+            iqr[i] = quantile(&values, 0.75) - quantile(&values, 0.25);
+        }
+
+        RobustScaler(Affine::new(median, iqr))
+    }
+
+    /// Inverse of [Scaler::save]
+    pub fn load(lines: &[&str]) -> Self {
+        RobustScaler(Affine::load(lines))
+    }
+}
+
+impl Scaler for StandardScaler {
+    fn transform(&self, x: &Matrix) -> Matrix {
+        self.0.transform(x)
+    }
+
+    fn inverse_transform(&self, x: &Matrix) -> Matrix {
+        self.0.inverse_transform(x)
+    }
+
+    fn name(&self) -> &str {
+        "STANDARD"
+    }
+
+    fn save(&self) -> Vec<String> {
+        self.0.save()
+    }
+}
+
+impl Scaler for MinMaxScaler {
+    fn transform(&self, x: &Matrix) -> Matrix {
+        self.0.transform(x)
+    }
+
+    fn inverse_transform(&self, x: &Matrix) -> Matrix {
+        self.0.inverse_transform(x)
+    }
+
+    fn name(&self) -> &str {
+        "MIN_MAX"
+    }
+
+    fn save(&self) -> Vec<String> {
+        self.0.save()
+    }
+}
+
+impl Scaler for RobustScaler {
+    fn transform(&self, x: &Matrix) -> Matrix {
+        self.0.transform(x)
+    }
+
+    fn inverse_transform(&self, x: &Matrix) -> Matrix {
+        self.0.inverse_transform(x)
+    }
+
+    fn name(&self) -> &str {
+        "ROBUST"
+    }
+
+    fn save(&self) -> Vec<String> {
+        self.0.save()
+    }
+}
+
+/// `(x - offset) / scale` for every row, shared by all scalers
+struct Affine {
+    offset: Matrix,
+    scale: Matrix,
+}
+
+impl Affine {
+    /// Features that are constant in the training data are only shifted
+    fn new(offset: Matrix, scale: Matrix) -> Self {
+        let scale = scale.map(|s| if s > f32::EPSILON { s } else { 1. });
+
+        Affine { offset, scale }
+    }
+
+    fn load(lines: &[&str]) -> Self {
+        Affine {
+            offset: matrix_from_string(lines[0]),
+            scale: matrix_from_string(lines[1]),
+        }
+    }
+
+    fn transform(&self, x: &Matrix) -> Matrix {
+        assert_eq!(x.nrows(), self.offset.nrows(), "wrong number of features");
+
+        Matrix::from_fn(x.nrows(), x.ncols(), |i, j| {
+            (x[(i, j)] - self.offset[i]) / self.scale[i]
+        })
+    }
+
+    fn inverse_transform(&self, x: &Matrix) -> Matrix {
+        assert_eq!(x.nrows(), self.offset.nrows(), "wrong number of features");
+
+        Matrix::from_fn(x.nrows(), x.ncols(), |i, j| {
+            x[(i, j)] * self.scale[i] + self.offset[i]
+        })
+    }
+
+    fn save(&self) -> Vec<String> {
+        vec![
+            matrix_to_string(&self.offset),
+            matrix_to_string(&self.scale),
+        ]
+    }
+}
+
+/// Linear interpolation between the closest ranks of sorted values
+fn quantile(sorted: &[f32], q: f32) -> f32 {
+    let pos = q * (sorted.len() - 1) as f32;
+    let (lo, hi) = (pos.floor() as usize, pos.ceil() as usize);
+
+    sorted[lo] + (sorted[hi] - sorted[lo]) * (pos - lo as f32)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data::{MinMaxScaler, RobustScaler, Scaler, StandardScaler};
+    use nalgebra::dmatrix;
+
+    #[test]
+    fn test_scalers() {
+        let x = dmatrix![
+            1., 2., 3., 4., 100.;
+            5., 5., 5., 5., 5.;
+        ];
+
+        let standard = StandardScaler::fit(&x).transform(&x);
+        assert!(standard.row(0).mean().abs() < 1e-5);
+        assert!((standard.row(0).variance() - 1.).abs() < 1e-5);
+        assert_eq!(standard.row(1), dmatrix![0., 0., 0., 0., 0.]);
+
+        let min_max = MinMaxScaler::fit(&x).transform(&x);
+        assert_eq!(min_max.row(0).min(), 0.);
+        assert_eq!(min_max.row(0).max(), 1.);
+
+        // median 3 and interquartile range 2 ignore the outlier
+        let robust = RobustScaler::fit(&x);
+        assert_eq!(
+            robust.transform(&x).row(0),
+            dmatrix![-1., -0.5, 0., 0.5, 48.5]
+        );
+        assert_eq!(robust.inverse_transform(&robust.transform(&x)), x);
+    }
+}
@@ -0,0 +1,326 @@
+use std::collections::BTreeMap;
+
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+
+use crate::{
+    data::{DataLoader, MatrixDataset},
+    nn::NNBuilder,
+    Matrix,
+};
+
+/// How [train_val_test_split] divides the samples
+pub struct SplitOptions {
+    /// fraction of the samples used for validation
+    pub validation: f32,
+    /// fraction of the samples used for testing
+    pub test: f32,
+    /// keep the share of every class the same in all parts, the class of a
+    /// one-hot label is its largest row, that of a single row label its value
+    pub stratify: bool,
+    pub shuffle: bool,
+    /// the same seed gives the same split, a random one is used if `None`
+    pub seed: Option<u64>,
+}
+
+impl Default for SplitOptions {
+    fn default() -> Self {
+        SplitOptions {
+            validation: 0.1,
+            test: 0.2,
+            stratify: false,
+            shuffle: true,
+            seed: None,
+        }
+    }
+}
+
+/// The samples of every part of a [train_val_test_split], one per column
+pub struct Split {
+    pub x_train: Matrix,
+    pub y_train: Matrix,
+    pub x_val: Matrix,
+    pub y_val: Matrix,
+    pub x_test: Matrix,
+    pub y_test: Matrix,
+}
+
+pub fn train_val_test_split(x: &Matrix, y: &Matrix, options: &SplitOptions) -> Split {
+    let (train, val, test) = split_indices(y, options);
+
+    Split {
+        x_train: x.select_columns(&train),
+        y_train: y.select_columns(&train),
+        x_val: x.select_columns(&val),
+        y_val: y.select_columns(&val),
+        x_test: x.select_columns(&test),
+        y_test: y.select_columns(&test),
+    }
+}
+
+/// The columns of the train, validation and test parts
+pub fn split_indices(y: &Matrix, options: &SplitOptions) -> (Vec<usize>, Vec<usize>, Vec<usize>) {
+    assert!(options.validation >= 0. && options.test >= 0.);
+    assert!(options.validation + options.test <= 1.);
+
+    let (mut train, mut val, mut test) = (vec![], vec![], vec![]);
+    let mut rng = rng(options.seed);
+
+    for group in groups(y, options.stratify, options.shuffle, &mut rng) {
+        let num_test = (group.len() as f32 * options.test).round() as usize;
+        let num_val = (group.len() as f32 * options.validation).round() as usize;
+        let num_val = num_val.min(group.len() - num_test);
+
+        test.extend_from_slice(&group[..num_test]);
+        val.extend_from_slice(&group[num_test..num_test + num_val]);
+        train.extend_from_slice(&group[num_test + num_val..]);
+    }
+
+    for part in [&mut train, &mut val, &mut test] {
+        if options.shuffle {
+            part.shuffle(&mut rng);
+        } else {
+            part.sort();
+        }
+    }
+
+    (train, val, test)
+}
+
+/// The train and validation columns of every fold, every sample is used
+/// for validation exactly once. The same `seed` gives the same folds
+pub fn k_fold_indices(
+    y: &Matrix,
+    k: usize,
+    stratify: bool,
+    shuffle: bool,
+    seed: Option<u64>,
+) -> Vec<(Vec<usize>, Vec<usize>)> {
+    assert!(
+        k > 1 && k <= y.ncols(),
+        "k must be between 2 and the number of samples"
+    );
+
+    let mut folds = vec![vec![]; k];
+    let mut next = 0;
+
+    // dealing the samples out keeps the folds within one sample of each other
+    for group in groups(y, stratify, shuffle, &mut rng(seed)) {
+        for i in group {
+            folds[next].push(i);
+            next = (next + 1) % k;
+        }
+    }
+
+    (0..k)
+        .map(|f| {
+            let mut val = folds[f].clone();
+            let mut train = (0..k)
+                .filter(|&g| g != f)
+                .flat_map(|g| folds[g].iter().copied())
+                .collect::<Vec<_>>();
+            train.sort();
+            val.sort();
+
+            (train, val)
+        })
+        .collect()
+}
+
+/// Results of a trained network on its validation fold
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FoldMetrics {
+    /// per sample loss of the first head
+    pub loss: f32,
+    pub accuracy: f32,
+}
+
+pub struct CrossValidation {
+    pub folds: Vec<FoldMetrics>,
+}
+
+impl CrossValidation {
+    pub fn mean(&self) -> FoldMetrics {
+        let n = self.folds.len() as f32;
+
+        FoldMetrics {
+            loss: self.folds.iter().map(|f| f.loss).sum::<f32>() / n,
+            accuracy: self.folds.iter().map(|f| f.accuracy).sum::<f32>() / n,
+        }
+    }
+
+    /// Standard deviation of the metrics over the folds
+    pub fn std(&self) -> FoldMetrics {
+        let mean = self.mean();
+        let n = self.folds.len() as f32;
+        let std = |f: fn(&FoldMetrics) -> f32, mean: f32| {
+            (self
+                .folds
+                .iter()
+                .map(|x| (f(x) - mean).powi(2))
+                .sum::<f32>()
+                / n)
+                .sqrt()
+        };
+
+        FoldMetrics {
+            loss: std(|f| f.loss, mean.loss),
+            accuracy: std(|f| f.accuracy, mean.accuracy),
+        }
+    }
+}
+
+/// Train a fresh network from `builder` on every fold of a k-fold split and
+/// evaluate it on the samples it did not see. The validation fold is also
+/// the test set while training, see [NNOptions::test](crate::nn::NNOptions::test).
+/// The samples are shuffled into folds with `seed`, see [k_fold_indices]
+pub fn cross_validate<F: Fn() -> NNBuilder>(
+    builder: F,
+    x: &Matrix,
+    y: &Matrix,
+    k: usize,
+    stratify: bool,
+    seed: Option<u64>,
+) -> CrossValidation {
+    let folds = k_fold_indices(y, k, stratify, true, seed)
+        .into_iter()
+        .map(|(train, val)| {
+            let (x_train, y_train) = (x.select_columns(&train), [y.select_columns(&train)]);
+            let (x_val, y_val) = (x.select_columns(&val), [y.select_columns(&val)]);
+
+            let mut nn = builder().build();
+            let loader = DataLoader::new(
+                MatrixDataset::new(&x_train, &y_train),
+                nn.options.batch_size,
+            );
+            nn.fit(&loader, &MatrixDataset::new(&x_val, &y_val));
+
+            FoldMetrics {
+                loss: nn.evaluate(&x_val, &y_val)[0],
+                accuracy: nn.accuracy(&x_val, &y_val[0]),
+            }
+        })
+        .collect();
+
+    CrossValidation { folds }
+}
+
+fn rng(seed: Option<u64>) -> StdRng {
+    match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    }
+}
+
+/// Sample indices grouped by class, or all in one group
+fn groups(y: &Matrix, stratify: bool, shuffle: bool, rng: &mut StdRng) -> Vec<Vec<usize>> {
+    let mut groups = BTreeMap::<i64, Vec<usize>>::new();
+
+    for (j, label) in y.column_iter().enumerate() {
+        let class = match (stratify, label.nrows()) {
+            (false, _) => 0,
+            (true, 1) => label[0].round() as i64,
+            (true, _) => label.argmax().0 as i64,
+        };
+        groups.entry(class).or_default().push(j);
+    }
+
+    let mut groups = groups.into_values().collect::<Vec<_>>();
+    if shuffle {
+        for group in &mut groups {
+            group.shuffle(rng);
+        }
+    }
+
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        activation::ActivationType,
+        data::{cross_validate, k_fold_indices, split_indices, SplitOptions},
+        nn::{NNBuilder, NNOptions, StopCondition},
+        Matrix,
+    };
+
+    #[test]
+    fn test_split() {
+        // 8 samples of class 0 and 2 of class 1
+        let y = Matrix::from_fn(1, 10, |_, j| (j >= 8) as u8 as f32);
+        let options = SplitOptions {
+            validation: 0.5,
+            test: 0.,
+            stratify: true,
+            shuffle: true,
+            seed: None,
+        };
+
+        let (train, val, test) = split_indices(&y, &options);
+        assert_eq!((train.len(), val.len(), test.len()), (5, 5, 0));
+        assert_eq!(train.iter().filter(|&&i| i >= 8).count(), 1);
+        assert_eq!(val.iter().filter(|&&i| i >= 8).count(), 1);
+
+        let folds = k_fold_indices(&y, 3, true, false, None);
+        let mut seen = folds
+            .iter()
+            .flat_map(|(_, val)| val.clone())
+            .collect::<Vec<_>>();
+        seen.sort();
+        assert_eq!(seen, (0..10).collect::<Vec<_>>());
+        for (train, val) in &folds {
+            assert_eq!(train.len() + val.len(), 10);
+            assert!(val.iter().all(|i| !train.contains(i)));
+        }
+
+        let x = Matrix::from_fn(2, 12, |i, j| ((i * 3 + j * 5) % 7) as f32 / 7.);
+        let y = Matrix::from_fn(2, 12, |i, j| (i == j % 2) as u8 as f32);
+        let results = cross_validate(
+            || {
+                NNBuilder::new(2)
+                    .options(NNOptions {
+                        log_interval: None,
+                        log_batches: false,
+                        test: false,
+                        batch_size: 4,
+                        stop_condition: StopCondition::Epoch(2),
+                        ..Default::default()
+                    })
+                    .add_layer(2, ActivationType::Sigmoid)
+            },
+            &x,
+            &y,
+            3,
+            true,
+            None,
+        );
+        assert_eq!(results.folds.len(), 3);
+        assert!(results.mean().loss.is_finite());
+    }
+
+    #[test]
+    fn test_seeded_split() {
+        let y = Matrix::from_fn(1, 20, |_, j| (j % 3) as f32);
+        let options = |seed| SplitOptions {
+            stratify: true,
+            seed: Some(seed),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            split_indices(&y, &options(1)),
+            split_indices(&y, &options(1))
+        );
+        assert_ne!(
+            split_indices(&y, &options(1)),
+            split_indices(&y, &options(2))
+        );
+        assert_eq!(
+            k_fold_indices(&y, 4, true, true, Some(1)),
+            k_fold_indices(&y, 4, true, true, Some(1))
+        );
+        assert_ne!(
+            k_fold_indices(&y, 4, true, true, Some(1)),
+            k_fold_indices(&y, 4, true, true, Some(2))
+        );
+    }
+}
@@ -0,0 +1,128 @@
+use crate::{nn::NN, Matrix};
+
+/// Compare the gradients computed by backpropagation with central finite
+/// differences of the training loss, the weighted sum of the losses of all
+/// heads with one label matrix per head, for every trainable parameter.
+///
+/// The network is run in training mode, so dropout and batch statistics are
+/// checked as well. Every loss is computed on a fresh single threaded copy,
+/// which draws the same dropout masks, and the network is left as it was.
+/// Returns the largest relative error found in each layer, weight decay is
+/// not part of the checked loss.
+pub fn gradient_check(nn: &NN, x: &Matrix, labels: &[Matrix], eps: f32) -> Vec<f32> {
+    assert_eq!(labels.len(), nn.heads.len(), "one label matrix per head");
+    let x = nn.scale(x).into_owned();
+
+    let mut copy = nn.replica();
+    copy.forward(&x, true);
+    let predicted = copy.head_outputs(&x);
+    copy.back_propagate_heads(&x, labels, &predicted, None);
+
+    let mut errors = vec![];
+
+    for i in 0..copy.layers.len() {
+        let mut max_error = 0f32;
+
+        for (p, parameter) in copy.layers[i].parameters().iter().enumerate() {
+            for j in 0..parameter.value.len() {
+                let loss = |delta: f32| {
+                    let mut perturbed = nn.replica();
+                    perturbed.layers[i].parameters_mut()[p].value[j] += delta;
+                    loss(&mut perturbed, &x, labels)
+                };
+
+                let numeric = ((loss(eps) - loss(-eps)) / (2. * eps as f64)) as f32;
+
+                max_error = max_error.max(relative_error(parameter.gradient[j], numeric));
+            }
+        }
+
+        errors.push(max_error);
+    }
+
+    errors
+}
+
+/// Overwrite every parameter with a fixed pattern of values in `[-0.4, 0.5]`,
+/// so that checks are reproducible and stay away from the flat regions
+/// where f32 finite differences are mostly noise
+#[cfg(test)]
+pub(crate) fn fill_deterministic(nn: &mut NN) {
+    for layer in &mut nn.layers {
+        for parameter in layer.parameters_mut() {
+            let (rows, cols) = parameter.value.shape();
+            parameter.value =
+                Matrix::from_fn(rows, cols, |i, j| ((i * 3 + j * 5) % 9) as f32 / 9. - 0.4);
+        }
+    }
+}
+
+fn loss(nn: &mut NN, x: &Matrix, labels: &[Matrix]) -> f64 {
+    nn.forward(x, true);
+    let predicted = nn.head_outputs(x);
+
+    nn.heads
+        .iter()
+        .zip(predicted.iter().zip(labels))
+        .map(|(head, (p, y))| {
+            let loss = p
+                .iter()
+                .zip(y.iter())
+                .map(|(&p, &y)| head.loss.element_loss(p, y) as f64)
+                .sum::<f64>();
+            head.weight as f64 * loss
+        })
+        .sum()
+}
+
+fn relative_error(analytic: f32, numeric: f32) -> f32 {
+    let denominator = (analytic.abs() + numeric.abs()).max(1e-4);
+
+    (analytic - numeric).abs() / denominator
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{activation::ActivationType, gradient_check::gradient_check, nn::NNBuilder};
+    use nalgebra::dmatrix;
+
+    #[test]
+    fn test_gradient_check() {
+        let x = dmatrix![
+            0.3, -0.8, 0.5;
+            -0.4, 0.6, 0.9;
+        ];
+        let y = dmatrix![
+            0.1, 0.9, 0.4;
+            0.7, 0.2, 0.6;
+        ];
+
+        let activations = [
+            ActivationType::ReLu,
+            ActivationType::ReLuLeaky,
+            ActivationType::Sigmoid,
+        ];
+
+        for activation in activations {
+            let name = format!("{activation:?}");
+            let mut nn = NNBuilder::new(2)
+                .add_layer(4, activation)
+                .add_layer(2, ActivationType::Sigmoid)
+                .build();
+
+            // keep every pre-activation at least 2 * eps away from the kinks
+            // of the relu variants, where finite differences are meaningless
+            nn.layers[0].parameters_mut()[0].value = dmatrix![
+                0.5, -0.3;
+                0.8, 0.2;
+                -0.6, 0.7;
+                0.4, 0.9;
+            ];
+            nn.layers[0].parameters_mut()[1].value = dmatrix![0.1; -0.2; 0.3; 0.05];
+
+            for error in gradient_check(&nn, &x, std::slice::from_ref(&y), 1e-2) {
+                assert!(error < 5e-2, "{name}: relative error {error}");
+            }
+        }
+    }
+}
@@ -0,0 +1,235 @@
+use crate::{
+    activation::ActivationType,
+    layers::{Add, Concat, Dense, Layer, Shape},
+    loss::Loss,
+    nn::{Head, NNOptions, StopCondition, NN},
+    optimizers::{Optimizer, OptimizerType},
+};
+
+/// Handle to the output of a node in a [GraphBuilder]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Node(pub(crate) usize);
+
+/// Builds networks that are not a plain stack of layers, eg. with residual
+/// connections. Every layer is added on top of one or more existing nodes.
+/// The network predicts the nodes passed to [GraphBuilder::output], or the
+/// last node added if there are none
+#[derive(Default)]
+pub struct GraphBuilder {
+    layers: Vec<Box<dyn Layer>>,
+    inputs: Vec<Vec<usize>>,
+    /// shape of every node, starting with the input
+    shapes: Vec<Shape>,
+    heads: Vec<Head>,
+    options: NNOptions,
+    optimizer: Box<dyn Optimizer>,
+}
+
+impl GraphBuilder {
+    pub fn new(num_inputs: usize) -> Self {
+        GraphBuilder::with_input_shape(Shape::flat(num_inputs))
+    }
+
+    pub fn with_input_shape(shape: Shape) -> Self {
+        GraphBuilder {
+            shapes: vec![shape],
+            ..Default::default()
+        }
+    }
+
+    pub fn options(mut self, options: NNOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn optimizer(mut self, optimizer_type: OptimizerType) -> Self {
+        self.optimizer = optimizer_type.optimizer();
+        self
+    }
+
+    pub fn with_optimizer(mut self, optimizer: Box<dyn Optimizer>) -> Self {
+        self.optimizer = optimizer;
+        self
+    }
+
+    /// The input of the network
+    pub fn input(&self) -> Node {
+        Node(0)
+    }
+
+    pub fn shape(&self, node: Node) -> Shape {
+        self.shapes[node.0]
+    }
+
+    /// Add any layer reading from `input`, its input size must match the
+    /// size of that node
+    pub fn layer<L: Layer + 'static>(&mut self, input: Node, layer: L) -> Node {
+        self.push(vec![input.0], Box::new(layer))
+    }
+
+    pub fn dense(
+        &mut self,
+        input: Node,
+        num_neurons: usize,
+        activation_type: ActivationType,
+    ) -> Node {
+        let num_inputs = self.shape(input).len();
+
+        self.layer(input, Dense::new(num_inputs, num_neurons, activation_type))
+    }
+
+    /// Sum the outputs of nodes that all have the same shape
+    pub fn add(&mut self, inputs: &[Node]) -> Node {
+        let shape = self.shape(inputs[0]);
+        assert!(
+            inputs.iter().all(|&node| self.shape(node) == shape),
+            "only nodes of the same shape can be added"
+        );
+
+        self.push(
+            inputs.iter().map(|node| node.0).collect(),
+            Box::new(Add::new(inputs.len(), shape)),
+        )
+    }
+
+    /// Join the outputs of several nodes into one
+    pub fn concat(&mut self, inputs: &[Node]) -> Node {
+        let shapes = inputs
+            .iter()
+            .map(|&node| self.shape(node))
+            .collect::<Vec<_>>();
+
+        self.push(
+            inputs.iter().map(|node| node.0).collect(),
+            Box::new(Concat::new(Concat::output_shape_of(&shapes))),
+        )
+    }
+
+    /// Train the network to predict `node` with the given loss, weighted
+    /// against the losses of the other outputs. Labels for the outputs are
+    /// passed in the order they were added
+    pub fn output(&mut self, node: Node, loss: Loss, weight: f32) {
+        self.heads.push(Head::new(node.0, loss, weight));
+    }
+
+    pub(crate) fn push(&mut self, inputs: Vec<usize>, layer: Box<dyn Layer>) -> Node {
+        self.shapes.push(layer.output_shape());
+        self.inputs.push(inputs);
+        self.layers.push(layer);
+
+        Node(self.layers.len())
+    }
+
+    pub fn build(mut self) -> NN {
+        assert!(!self.layers.is_empty(), "the graph has no layers");
+
+        let mut optimizer = self.optimizer;
+        if let StopCondition::TestAccuracy(_) = self.options.stop_condition {
+            self.options.test = true;
+        }
+
+        for layer in &mut self.layers {
+            layer.register(optimizer.as_mut());
+        }
+
+        let mut nn = NN::new(self.layers, self.inputs, self.options, optimizer);
+        if !self.heads.is_empty() {
+            nn.heads = self.heads;
+        }
+
+        nn
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        activation::ActivationType,
+        gradient_check::{fill_deterministic, gradient_check},
+        graph::GraphBuilder,
+        loss::Loss,
+        nn::{NNOptions, StopCondition},
+        Matrix,
+    };
+    use nalgebra::dmatrix;
+
+    #[test]
+    fn test_residual_gradients() {
+        let x = dmatrix![
+            0.3, -0.8, 0.5;
+            -0.4, 0.6, 0.9;
+            0.2, 0.1, -0.7;
+        ];
+        let y = dmatrix![
+            0.1, 0.9, 0.4;
+            0.7, 0.2, 0.6;
+        ];
+
+        let mut graph = GraphBuilder::new(3);
+        let input = graph.input();
+        let a = graph.dense(input, 3, ActivationType::Sigmoid);
+        let b = graph.dense(a, 3, ActivationType::Sigmoid);
+        let residual = graph.add(&[a, b, input]);
+        let joined = graph.concat(&[residual, a]);
+        graph.dense(joined, 2, ActivationType::Sigmoid);
+
+        let mut nn = graph.build();
+        fill_deterministic(&mut nn);
+        assert_eq!(nn.feed_forward(&x).shape(), (2, 3));
+
+        for error in gradient_check(&nn, &x, std::slice::from_ref(&y), 1e-2) {
+            assert!(error < 5e-2, "relative error {error}");
+        }
+    }
+
+    #[test]
+    fn test_multiple_heads() {
+        let x = Matrix::from_fn(2, 8, |i, j| ((i * 3 + j * 5) % 7) as f32 / 7. - 0.5);
+        let class = Matrix::from_fn(1, 8, |_, j| (x[(0, j)] > 0.) as u8 as f32);
+        let value = Matrix::from_fn(1, 8, |_, j| 0.5 + 0.5 * x[(1, j)]);
+
+        let mut graph = GraphBuilder::new(2).options(NNOptions {
+            log_interval: None,
+            log_batches: false,
+            test: false,
+            batch_size: 4,
+            learning_rate: 0.5,
+            stop_condition: StopCondition::Epoch(300),
+            weight_decay: 0.,
+            ..Default::default()
+        });
+        let input = graph.input();
+        let trunk = graph.dense(input, 6, ActivationType::Sigmoid);
+        let class_head = graph.dense(trunk, 1, ActivationType::Sigmoid);
+        let value_head = graph.dense(trunk, 1, ActivationType::Sigmoid);
+        graph.output(class_head, Loss::CrossEntropy, 1.);
+        graph.output(value_head, Loss::MeanSquared, 2.);
+        let mut nn = graph.build();
+
+        let labels = [class, value];
+        let before = nn.evaluate(&x, &labels);
+        nn.train_heads(&x, &labels, &x, &labels);
+        let after = nn.evaluate(&x, &labels);
+
+        assert_eq!(nn.feed_forward_heads(&x).len(), 2);
+        for (before, after) in before.iter().zip(&after) {
+            assert!(after < before, "loss went from {before} to {after}");
+        }
+    }
+
+    #[test]
+    fn test_head_before_last_node() {
+        let x = Matrix::from_fn(2, 4, |i, j| (i + j) as f32 / 4.);
+
+        let mut graph = GraphBuilder::new(2);
+        let input = graph.input();
+        let head = graph.dense(input, 3, ActivationType::Sigmoid);
+        graph.dense(head, 1, ActivationType::Sigmoid);
+        graph.output(head, Loss::MeanSquared, 1.);
+        let mut nn = graph.build();
+
+        let predicted = nn.feed_forward(&x);
+        assert_eq!(predicted.shape(), (3, 4));
+        assert_eq!(predicted, nn.feed_forward_heads(&x).swap_remove(0));
+    }
+}
@@ -0,0 +1,363 @@
+use nalgebra::Dyn;
+use rand::thread_rng;
+use rand_distr::{Distribution, Normal};
+
+use crate::{
+    layers::{Layer, Shape},
+    parameter::Parameter,
+    storage::{matrix_from_string, matrix_to_string},
+    Matrix,
+};
+
+/// Multi-head scaled dot-product self-attention over a sequence, see
+/// [Shape::sequence]. Every timestep is projected to a query, key and value,
+/// split over the heads, and the attended values of all heads are projected
+/// back to the size of a timestep so the layer can sit in a residual block.
+///
+/// With `causal` set a timestep only attends to itself and earlier ones
+#[derive(Clone)]
+pub struct MultiHeadAttention {
+    pub(crate) wq: Parameter,
+    pub(crate) wk: Parameter,
+    pub(crate) wv: Parameter,
+    pub(crate) wo: Parameter,
+    pub(crate) bias: Parameter,
+
+    timesteps: usize,
+    num_heads: usize,
+    causal: bool,
+
+    pub(crate) a: Matrix,
+    /// per sample, the values needed for the backward pass
+    cache: Vec<Cache>,
+}
+
+#[derive(Clone)]
+struct Cache {
+    q: Matrix,
+    k: Matrix,
+    v: Matrix,
+    /// attention weights of every head, one row per query
+    weights: Vec<Matrix>,
+    /// outputs of all heads stacked, before the output projection
+    heads: Matrix,
+}
+
+impl MultiHeadAttention {
+    pub fn new(input_shape: Shape, num_heads: usize, causal: bool) -> Self {
+        assert_eq!(
+            input_shape.channels, 1,
+            "attention expects a sequence, see Shape::sequence"
+        );
+        let dim = input_shape.width;
+        assert!(
+            num_heads > 0 && dim.is_multiple_of(num_heads),
+            "the size of a timestep must be divisible by the number of heads"
+        );
+
+        MultiHeadAttention {
+            wq: Parameter::new(random_weights(dim), true),
+            wk: Parameter::new(random_weights(dim), true),
+            wv: Parameter::new(random_weights(dim), true),
+            wo: Parameter::new(random_weights(dim), true),
+            bias: Parameter::new(Matrix::zeros(dim, 1), false),
+            timesteps: input_shape.height,
+            num_heads,
+            causal,
+            a: Matrix::zeros(input_shape.len(), 0),
+            cache: vec![],
+        }
+    }
+
+    /// Inverse of [Layer::save]
+    pub fn load(lines: &[&str]) -> Self {
+        let config = lines[0].split(' ').collect::<Vec<_>>();
+        let timesteps = config[0].parse().unwrap();
+        let dim = config[1].parse().unwrap();
+        let num_heads = config[2].parse().unwrap();
+        let causal = config[3].parse().unwrap();
+
+        let mut layer = MultiHeadAttention::new(Shape::sequence(timesteps, dim), num_heads, causal);
+        layer.wq.value = matrix_from_string(lines[1]);
+        layer.wk.value = matrix_from_string(lines[2]);
+        layer.wv.value = matrix_from_string(lines[3]);
+        layer.wo.value = matrix_from_string(lines[4]);
+        layer.bias.value = matrix_from_string(lines[5]);
+
+        layer
+    }
+
+    fn dim(&self) -> usize {
+        self.bias.value.nrows()
+    }
+
+    fn head_dim(&self) -> usize {
+        self.dim() / self.num_heads
+    }
+
+    /// One timestep per column
+    fn unfold(&self, sample: &[f32]) -> Matrix {
+        Matrix::from_column_slice(self.dim(), self.timesteps, sample)
+    }
+
+    fn attend(&self, q: &Matrix, k: &Matrix, v: &Matrix) -> (Vec<Matrix>, Matrix) {
+        let d = self.head_dim();
+        let scale = 1. / (d as f32).sqrt();
+
+        let mut weights = vec![];
+        let mut heads = Matrix::zeros(self.dim(), self.timesteps);
+
+        for h in 0..self.num_heads {
+            let (qh, kh, vh) = (q.rows(h * d, d), k.rows(h * d, d), v.rows(h * d, d));
+
+            let mut scores = qh.tr_mul(&kh) * scale;
+            softmax_rows(&mut scores, self.causal);
+
+            heads
+                .rows_mut(h * d, d)
+                .copy_from(&(vh * scores.transpose()));
+            weights.push(scores);
+        }
+
+        (weights, heads)
+    }
+}
+
+impl Layer for MultiHeadAttention {
+    fn forward(&mut self, data: &Matrix, _training: bool) -> Matrix {
+        let mut a = Matrix::zeros(data.nrows(), data.ncols());
+        self.cache.clear();
+
+        for (sample, mut out) in data.column_iter().zip(a.column_iter_mut()) {
+            let x = self.unfold(sample.as_slice());
+            let q = &self.wq.value * &x;
+            let k = &self.wk.value * &x;
+            let v = &self.wv.value * &x;
+
+            let (weights, heads) = self.attend(&q, &k, &v);
+
+            let mut y = &self.wo.value * &heads;
+            for mut col in y.column_iter_mut() {
+                col += &self.bias.value;
+            }
+            out.copy_from_slice(y.as_slice());
+
+            self.cache.push(Cache {
+                q,
+                k,
+                v,
+                weights,
+                heads,
+            });
+        }
+
+        self.a = a;
+        self.a.clone()
+    }
+
+    fn backward(&mut self, delta: Matrix, input: &Matrix) -> Matrix {
+        let d = self.head_dim();
+        let scale = 1. / (d as f32).sqrt();
+        let dim = self.dim();
+
+        let mut dwq = Matrix::zeros(dim, dim);
+        let mut dwk = Matrix::zeros(dim, dim);
+        let mut dwv = Matrix::zeros(dim, dim);
+        let mut dwo = Matrix::zeros(dim, dim);
+        let mut db = Matrix::zeros(dim, 1);
+        let mut dx = Matrix::zeros(input.nrows(), input.ncols());
+
+        for (s, cache) in self.cache.iter().enumerate() {
+            let x = self.unfold(input.column(s).as_slice());
+            let dy = self.unfold(delta.column(s).as_slice());
+
+            dwo += &dy * cache.heads.transpose();
+            db += dy.column_sum().reshape_generic(Dyn(dim), Dyn(1));
+            let dheads = self.wo.value.tr_mul(&dy);
+
+            let mut dq = Matrix::zeros(dim, self.timesteps);
+            let mut dk = Matrix::zeros(dim, self.timesteps);
+            let mut dv = Matrix::zeros(dim, self.timesteps);
+
+            for (h, weights) in cache.weights.iter().enumerate() {
+                let (qh, kh, vh) = (
+                    cache.q.rows(h * d, d),
+                    cache.k.rows(h * d, d),
+                    cache.v.rows(h * d, d),
+                );
+                let dout = dheads.rows(h * d, d);
+
+                dv.rows_mut(h * d, d).copy_from(&(dout * weights));
+
+                // through the softmax of every row, masked weights are zero
+                // and stay zero
+                let mut dscores = dout.tr_mul(&vh);
+                for (mut drow, row) in dscores.row_iter_mut().zip(weights.row_iter()) {
+                    let dot = drow.dot(&row);
+                    drow.add_scalar_mut(-dot);
+                    drow.component_mul_assign(&row);
+                }
+                dscores *= scale;
+
+                dq.rows_mut(h * d, d).copy_from(&(kh * dscores.transpose()));
+                dk.rows_mut(h * d, d).copy_from(&(qh * &dscores));
+            }
+
+            dwq += &dq * x.transpose();
+            dwk += &dk * x.transpose();
+            dwv += &dv * x.transpose();
+
+            let dxs =
+                self.wq.value.tr_mul(&dq) + self.wk.value.tr_mul(&dk) + self.wv.value.tr_mul(&dv);
+            dx.column_mut(s).copy_from_slice(dxs.as_slice());
+        }
+
+        if !self.wq.options.frozen {
+            self.wq.accumulate(&dwq);
+            self.wk.accumulate(&dwk);
+            self.wv.accumulate(&dwv);
+            self.wo.accumulate(&dwo);
+            self.bias.accumulate(&db);
+        }
+
+        dx
+    }
+
+    fn output(&self) -> &Matrix {
+        &self.a
+    }
+
+    fn num_outputs(&self) -> usize {
+        self.timesteps * self.dim()
+    }
+
+    fn output_shape(&self) -> Shape {
+        Shape::sequence(self.timesteps, self.dim())
+    }
+
+    fn parameters(&self) -> Vec<&Parameter> {
+        vec![&self.wq, &self.wk, &self.wv, &self.wo, &self.bias]
+    }
+
+    fn parameters_mut(&mut self) -> Vec<&mut Parameter> {
+        vec![
+            &mut self.wq,
+            &mut self.wk,
+            &mut self.wv,
+            &mut self.wo,
+            &mut self.bias,
+        ]
+    }
+
+    fn name(&self) -> &str {
+        "ATTENTION"
+    }
+
+    fn save(&self) -> Vec<String> {
+        vec![
+            format!(
+                "{} {} {} {}",
+                self.timesteps,
+                self.dim(),
+                self.num_heads,
+                self.causal
+            ),
+            matrix_to_string(&self.wq.value),
+            matrix_to_string(&self.wk.value),
+            matrix_to_string(&self.wv.value),
+            matrix_to_string(&self.wo.value),
+            matrix_to_string(&self.bias.value),
+        ]
+    }
+
+    fn replicate(&self) -> Box<dyn Layer> {
+        Box::new(MultiHeadAttention {
+            wq: self.wq.clone(),
+            wk: self.wk.clone(),
+            wv: self.wv.clone(),
+            wo: self.wo.clone(),
+            bias: self.bias.clone(),
+            timesteps: self.timesteps,
+            num_heads: self.num_heads,
+            causal: self.causal,
+            a: Matrix::zeros(self.a.nrows(), 0),
+            cache: vec![],
+        })
+    }
+}
+
+/// Softmax over every row, with `causal` set the entries right of the
+/// diagonal are left out and become zero
+fn softmax_rows(m: &mut Matrix, causal: bool) {
+    for i in 0..m.nrows() {
+        let len = if causal { i + 1 } else { m.ncols() };
+        let mut row = m.view_mut((i, 0), (1, len));
+
+        let max = row.max();
+        row.apply(|x| *x = (*x - max).exp());
+        row /= row.sum();
+
+        m.view_mut((i, len), (1, m.ncols() - len)).fill(0.);
+    }
+}
+
+fn random_weights(dim: usize) -> Matrix {
+    let distr = Normal::new(0., (1. / dim as f32).sqrt()).unwrap();
+
+    let mut rng = thread_rng();
+    Matrix::from_fn(dim, dim, |_, _| distr.sample(&mut rng))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        activation::ActivationType,
+        gradient_check::{fill_deterministic, gradient_check},
+        graph::GraphBuilder,
+        layers::{Layer, MultiHeadAttention, Shape},
+        Matrix,
+    };
+
+    #[test]
+    fn test_attention_gradients() {
+        let shape = Shape::sequence(3, 4);
+        let x = Matrix::from_fn(shape.len(), 2, |i, j| {
+            ((i * 5 + j * 3) % 7) as f32 / 7. - 0.5
+        });
+        let y = Matrix::from_fn(2, 2, |i, j| ((i + j) % 2) as f32);
+
+        for causal in [false, true] {
+            // a residual block around the attention
+            let mut graph = GraphBuilder::with_input_shape(shape);
+            let input = graph.input();
+            let attention = graph.layer(input, MultiHeadAttention::new(shape, 2, causal));
+            let residual = graph.add(&[input, attention]);
+            graph.dense(residual, 2, ActivationType::Sigmoid);
+            let mut nn = graph.build();
+
+            fill_deterministic(&mut nn);
+
+            // smaller steps are lost in the f32 rounding of the softmax
+            for error in gradient_check(&nn, &x, std::slice::from_ref(&y), 3e-2) {
+                assert!(error < 5e-2, "causal {causal}: relative error {error}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_causal_mask() {
+        let shape = Shape::sequence(3, 2);
+        let mut layer = MultiHeadAttention::new(shape, 1, true);
+
+        let x = Matrix::from_fn(shape.len(), 1, |i, _| i as f32 / 6.);
+        let mut changed = x.clone();
+        changed[5] += 1.;
+
+        let a = layer.forward(&x, false);
+        let b = layer.forward(&changed, false);
+
+        // only the last timestep sees the last input
+        assert_eq!(a.rows(0, 4), b.rows(0, 4));
+        assert_ne!(a.rows(4, 2), b.rows(4, 2));
+    }
+}
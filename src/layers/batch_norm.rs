@@ -0,0 +1,265 @@
+use nalgebra::Dyn;
+
+use crate::{
+    layers::Layer,
+    parameter::Parameter,
+    storage::{matrix_from_string, matrix_to_string},
+    Matrix,
+};
+
+const EPSILON: f32 = 1e-5;
+
+/// Normalizes every feature (row) over the samples of a batch, then scales
+/// and shifts it by the learnable `gamma` and `beta`
+#[derive(Clone)]
+pub struct BatchNorm {
+    pub(crate) gamma: Parameter,
+    pub(crate) beta: Parameter,
+    /// statistics used at inference time, updated while training
+    pub(crate) running_mean: Matrix,
+    pub(crate) running_var: Matrix,
+    /// fraction of the running statistics kept after every training batch
+    pub(crate) momentum: f32,
+
+    pub(crate) a: Matrix,
+    x_hat: Matrix,
+    inv_std: Matrix,
+    /// whether the last step normalized with the statistics of its batch
+    batch_statistics: bool,
+}
+
+impl BatchNorm {
+    pub fn new(num_features: usize) -> Self {
+        BatchNorm {
+            gamma: Parameter::new(Matrix::from_element(num_features, 1, 1.), false),
+            beta: Parameter::new(Matrix::zeros(num_features, 1), false),
+            running_mean: Matrix::zeros(num_features, 1),
+            running_var: Matrix::from_element(num_features, 1, 1.),
+            momentum: 0.9,
+            a: Matrix::zeros(num_features, 0),
+            x_hat: Matrix::zeros(num_features, 0),
+            inv_std: Matrix::zeros(num_features, 1),
+            batch_statistics: false,
+        }
+    }
+
+    /// Inverse of [Layer::save]
+    pub fn load(lines: &[&str]) -> Self {
+        let gamma = matrix_from_string(lines[0]);
+
+        let mut layer = BatchNorm::new(gamma.nrows());
+        layer.gamma.value = gamma;
+        layer.beta.value = matrix_from_string(lines[1]);
+        layer.running_mean = matrix_from_string(lines[2]);
+        layer.running_var = matrix_from_string(lines[3]);
+        layer.momentum = lines[4].parse().unwrap();
+
+        layer
+    }
+}
+
+impl Layer for BatchNorm {
+    fn forward(&mut self, data: &Matrix, training: bool) -> Matrix {
+        let (mean, var) = if training {
+            let mean = data
+                .column_mean()
+                .reshape_generic(Dyn(data.nrows()), Dyn(1));
+            let var = data
+                .column_variance()
+                .reshape_generic(Dyn(data.nrows()), Dyn(1));
+
+            self.running_mean = self.momentum * &self.running_mean + (1. - self.momentum) * &mean;
+            self.running_var = self.momentum * &self.running_var + (1. - self.momentum) * &var;
+
+            (mean, var)
+        } else {
+            (self.running_mean.clone(), self.running_var.clone())
+        };
+
+        self.batch_statistics = training;
+        self.inv_std = var.map(|v| 1. / (v + EPSILON).sqrt());
+
+        self.x_hat = data.clone_owned();
+        for mut col in self.x_hat.column_iter_mut() {
+            col -= &mean;
+            col.component_mul_assign(&self.inv_std);
+        }
+
+        self.a = self.x_hat.clone();
+        for mut col in self.a.column_iter_mut() {
+            col.component_mul_assign(&self.gamma.value);
+            col += &self.beta.value;
+        }
+
+        self.a.clone()
+    }
+
+    fn backward(&mut self, delta: Matrix, _input: &Matrix) -> Matrix {
+        let nrows = delta.nrows();
+
+        if !self.gamma.options.frozen {
+            let dgamma = delta
+                .component_mul(&self.x_hat)
+                .column_sum()
+                .reshape_generic(Dyn(nrows), Dyn(1));
+            let dbeta = delta.column_sum().reshape_generic(Dyn(nrows), Dyn(1));
+
+            self.gamma.accumulate(&dgamma);
+            self.beta.accumulate(&dbeta);
+        }
+
+        let mut dx_hat = delta;
+        for mut col in dx_hat.column_iter_mut() {
+            col.component_mul_assign(&self.gamma.value);
+        }
+
+        // with the running statistics the layer is a plain affine transform
+        if !self.batch_statistics {
+            for mut col in dx_hat.column_iter_mut() {
+                col.component_mul_assign(&self.inv_std);
+            }
+            return dx_hat;
+        }
+
+        let m = dx_hat.ncols() as f32;
+        let sum = dx_hat.column_sum();
+        let sum_x_hat = dx_hat.component_mul(&self.x_hat).column_sum();
+
+        let mut dx = dx_hat;
+        for (mut col, x_hat) in dx.column_iter_mut().zip(self.x_hat.column_iter()) {
+            for i in 0..nrows {
+                col[i] = self.inv_std[i] / m * (m * col[i] - sum[i] - x_hat[i] * sum_x_hat[i]);
+            }
+        }
+
+        dx
+    }
+
+    fn output(&self) -> &Matrix {
+        &self.a
+    }
+
+    fn num_outputs(&self) -> usize {
+        self.beta.value.nrows()
+    }
+
+    fn parameters(&self) -> Vec<&Parameter> {
+        vec![&self.gamma, &self.beta]
+    }
+
+    fn parameters_mut(&mut self) -> Vec<&mut Parameter> {
+        vec![&mut self.gamma, &mut self.beta]
+    }
+
+    fn name(&self) -> &str {
+        "BATCH_NORM"
+    }
+
+    fn save(&self) -> Vec<String> {
+        vec![
+            matrix_to_string(&self.gamma.value),
+            matrix_to_string(&self.beta.value),
+            matrix_to_string(&self.running_mean),
+            matrix_to_string(&self.running_var),
+            self.momentum.to_string(),
+        ]
+    }
+
+    fn replicate(&self) -> Box<dyn Layer> {
+        Box::new(BatchNorm {
+            gamma: self.gamma.clone(),
+            beta: self.beta.clone(),
+            running_mean: self.running_mean.clone(),
+            running_var: self.running_var.clone(),
+            momentum: self.momentum,
+            a: Matrix::zeros(self.a.nrows(), 0),
+            x_hat: Matrix::zeros(self.x_hat.nrows(), 0),
+            inv_std: self.inv_std.clone(),
+            batch_statistics: self.batch_statistics,
+        })
+    }
+
+    fn mixes_samples(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        activation::ActivationType,
+        gradient_check::{fill_deterministic, gradient_check},
+        layers::{BatchNorm, Layer},
+        nn::NNBuilder,
+        Matrix,
+    };
+    use nalgebra::dmatrix;
+
+    /// the input gradient against finite differences of
+    /// `sum(weights * forward(x))`, every perturbed batch has new statistics
+    #[test]
+    fn test_batch_norm_backprop() {
+        let x = dmatrix![
+            0.3, -0.8, 0.5, 1.2;
+            -0.4, 0.6, 0.9, 0.1;
+        ];
+        let weights = dmatrix![
+            0.2, -0.5, 0.7, 0.1;
+            0.9, 0.3, -0.6, 0.4;
+        ];
+        let eps = 1e-2;
+
+        let mut layer = BatchNorm::new(2);
+        layer.gamma.value = dmatrix![1.5; 0.5];
+        layer.beta.value = dmatrix![0.1; -0.2];
+
+        let loss = |layer: &mut BatchNorm, x: &Matrix| -> f32 {
+            layer.forward(x, true).component_mul(&weights).sum()
+        };
+
+        loss(&mut layer, &x);
+        let dx = layer.backward(weights.clone(), &x);
+
+        for i in 0..x.len() {
+            let mut x_plus = x.clone();
+            x_plus[i] += eps;
+            let mut x_minus = x.clone();
+            x_minus[i] -= eps;
+
+            let numeric = (loss(&mut layer, &x_plus) - loss(&mut layer, &x_minus)) / (2. * eps);
+            assert!((numeric - dx[i]).abs() < 1e-2, "{numeric} {}", dx[i]);
+        }
+    }
+
+    /// in training mode the gradients of the layer before also flow through
+    /// the mean and variance of the batch
+    #[test]
+    fn test_batch_norm_gradients() {
+        let x = dmatrix![
+            0.3, -0.8, 0.5, 1.2;
+            -0.4, 0.6, 0.9, 0.1;
+        ];
+        let y = dmatrix![
+            0.1, 0.9, 0.4, 0.5;
+            0.7, 0.2, 0.6, 0.3;
+        ];
+
+        let mut nn = NNBuilder::new(2)
+            .add_layer(3, ActivationType::Sigmoid)
+            .add_batch_norm()
+            .add_layer(2, ActivationType::Sigmoid)
+            .build();
+        fill_deterministic(&mut nn);
+        nn.layers[0].parameters_mut()[0].value = dmatrix![
+            0.5, -0.3;
+            0.8, 0.2;
+            -0.6, 0.7;
+        ];
+        nn.layers[1].parameters_mut()[0].value = dmatrix![1.5; 0.5; -0.7];
+        nn.layers[1].parameters_mut()[1].value = dmatrix![0.1; -0.2; 0.3];
+
+        for error in gradient_check(&nn, &x, std::slice::from_ref(&y), 1e-2) {
+            assert!(error < 5e-2, "relative error {error}");
+        }
+    }
+}
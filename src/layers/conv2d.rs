@@ -0,0 +1,337 @@
+use std::str::FromStr;
+
+use nalgebra::Dyn;
+use rand::thread_rng;
+use rand_distr::{Distribution, Normal};
+
+use crate::{
+    activation::{Activation, ActivationType},
+    empty_like,
+    layers::{Layer, Shape},
+    parameter::Parameter,
+    storage::{matrix_from_string, matrix_to_string},
+    Matrix,
+};
+
+/// 2D convolution over images laid out as described by [Shape], followed by
+/// an activation. Every filter spans all input channels and produces one
+/// output channel.
+///
+/// The input patches are unrolled into the columns of one matrix (im2col) so
+/// that the whole batch is convolved with a single matrix product.
+#[derive(Clone)]
+pub struct Conv2D {
+    pub(crate) weights: Parameter,
+    pub(crate) bias: Parameter,
+    pub(crate) activation: Activation,
+
+    input_shape: Shape,
+    output_shape: Shape,
+    kernel_size: usize,
+    stride: usize,
+    padding: usize,
+
+    pub(crate) a: Matrix,
+    pub(crate) z: Matrix,
+    /// unrolled input patches of the last forward pass
+    columns: Matrix,
+}
+
+impl Conv2D {
+    pub fn new(
+        input_shape: Shape,
+        filters: usize,
+        kernel_size: usize,
+        stride: usize,
+        padding: usize,
+        activation_type: ActivationType,
+    ) -> Self {
+        assert!(stride > 0);
+        assert!(
+            input_shape.height + 2 * padding >= kernel_size
+                && input_shape.width + 2 * padding >= kernel_size,
+            "kernel does not fit in the padded input"
+        );
+
+        let output_shape = Shape::new(
+            filters,
+            (input_shape.height + 2 * padding - kernel_size) / stride + 1,
+            (input_shape.width + 2 * padding - kernel_size) / stride + 1,
+        );
+        let patch_size = input_shape.channels * kernel_size * kernel_size;
+
+        Conv2D {
+            weights: Parameter::new(random_weights(filters, patch_size), true),
+            bias: Parameter::new(Matrix::zeros(filters, 1), false),
+            activation: activation_type.activation(),
+            input_shape,
+            output_shape,
+            kernel_size,
+            stride,
+            padding,
+            a: Matrix::zeros(output_shape.len(), 0),
+            z: Matrix::zeros(output_shape.len(), 0),
+            columns: Matrix::zeros(patch_size, 0),
+        }
+    }
+
+    /// Inverse of [Layer::save]
+    pub fn load(lines: &[&str]) -> Self {
+        let input_shape = Shape::from_string(lines[0]);
+        let config = lines[1]
+            .split(' ')
+            .map(|x| x.parse::<usize>().unwrap())
+            .collect::<Vec<_>>();
+        let weights = matrix_from_string(lines[2]);
+        let bias = matrix_from_string(lines[3]);
+        let activation_type = ActivationType::from_str(lines[4]).unwrap();
+
+        let mut layer = Conv2D::new(
+            input_shape,
+            config[0],
+            config[1],
+            config[2],
+            config[3],
+            activation_type,
+        );
+        layer.weights.value = weights;
+        layer.bias.value = bias;
+
+        layer
+    }
+
+    /// Input row and column of a kernel position, None if it lies in the padding
+    fn source(&self, out: usize, k: usize, size: usize) -> Option<usize> {
+        (out * self.stride + k)
+            .checked_sub(self.padding)
+            .filter(|&i| i < size)
+    }
+
+    /// Unroll every patch the kernel covers into a column, the columns of
+    /// sample `s` start at `s * num_pixels`
+    fn im2col(&self, data: &Matrix) -> Matrix {
+        let k = self.kernel_size;
+        let num_pixels = self.output_shape.height * self.output_shape.width;
+        let mut columns = Matrix::zeros(self.columns.nrows(), num_pixels * data.ncols());
+
+        for (s, sample) in data.column_iter().enumerate() {
+            for oy in 0..self.output_shape.height {
+                for ox in 0..self.output_shape.width {
+                    let mut column =
+                        columns.column_mut(s * num_pixels + oy * self.output_shape.width + ox);
+
+                    for c in 0..self.input_shape.channels {
+                        for ky in 0..k {
+                            let Some(y) = self.source(oy, ky, self.input_shape.height) else {
+                                continue;
+                            };
+                            for kx in 0..k {
+                                if let Some(x) = self.source(ox, kx, self.input_shape.width) {
+                                    column[(c * k + ky) * k + kx] =
+                                        sample[self.input_shape.index(c, y, x)];
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        columns
+    }
+
+    /// Inverse of [Conv2D::im2col], overlapping patches are summed
+    fn col2im(&self, columns: &Matrix, num_samples: usize) -> Matrix {
+        let k = self.kernel_size;
+        let num_pixels = self.output_shape.height * self.output_shape.width;
+        let mut data = Matrix::zeros(self.input_shape.len(), num_samples);
+
+        for (s, mut sample) in data.column_iter_mut().enumerate() {
+            for oy in 0..self.output_shape.height {
+                for ox in 0..self.output_shape.width {
+                    let column = columns.column(s * num_pixels + oy * self.output_shape.width + ox);
+
+                    for c in 0..self.input_shape.channels {
+                        for ky in 0..k {
+                            let Some(y) = self.source(oy, ky, self.input_shape.height) else {
+                                continue;
+                            };
+                            for kx in 0..k {
+                                if let Some(x) = self.source(ox, kx, self.input_shape.width) {
+                                    sample[self.input_shape.index(c, y, x)] +=
+                                        column[(c * k + ky) * k + kx];
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        data
+    }
+}
+
+impl Layer for Conv2D {
+    fn forward(&mut self, data: &Matrix, _training: bool) -> Matrix {
+        let num_pixels = self.output_shape.height * self.output_shape.width;
+
+        self.columns = self.im2col(data);
+        let mut product = &self.weights.value * &self.columns;
+        for mut col in product.column_iter_mut() {
+            col += &self.bias.value;
+        }
+
+        // the product holds the pixels of sample s in columns s * num_pixels..,
+        // the output stores them filter by filter in the sample's own column
+        self.z = unsafe { empty_like((self.output_shape.len(), data.ncols())) };
+        for (s, mut col) in self.z.column_iter_mut().enumerate() {
+            for f in 0..self.output_shape.channels {
+                for p in 0..num_pixels {
+                    col[f * num_pixels + p] = product[(f, s * num_pixels + p)];
+                }
+            }
+        }
+
+        self.a = unsafe { empty_like(self.z.shape()) };
+        self.activation.func(&self.z, &mut self.a);
+
+        self.a.clone()
+    }
+
+    fn backward(&mut self, mut delta: Matrix, _input: &Matrix) -> Matrix {
+        let num_pixels = self.output_shape.height * self.output_shape.width;
+        let num_samples = delta.ncols();
+
+        let mut buffer = unsafe { empty_like(self.z.shape()) };
+        self.activation.derv(&self.z, &mut buffer);
+        delta.component_mul_assign(&buffer);
+
+        // back to one row per filter and one column per output pixel
+        let mut dz = unsafe { empty_like((self.output_shape.channels, num_pixels * num_samples)) };
+        for (s, col) in delta.column_iter().enumerate() {
+            for f in 0..self.output_shape.channels {
+                for p in 0..num_pixels {
+                    dz[(f, s * num_pixels + p)] = col[f * num_pixels + p];
+                }
+            }
+        }
+
+        if !self.weights.options.frozen {
+            let dw = &dz * self.columns.transpose();
+            let db = dz.column_sum().reshape_generic(Dyn(dz.nrows()), Dyn(1));
+
+            self.weights.accumulate(&dw);
+            self.bias.accumulate(&db);
+        }
+
+        let columns = self.weights.value.tr_mul(&dz);
+        self.col2im(&columns, num_samples)
+    }
+
+    fn output(&self) -> &Matrix {
+        &self.a
+    }
+
+    fn num_outputs(&self) -> usize {
+        self.output_shape.len()
+    }
+
+    fn output_shape(&self) -> Shape {
+        self.output_shape
+    }
+
+    fn parameters(&self) -> Vec<&Parameter> {
+        vec![&self.weights, &self.bias]
+    }
+
+    fn parameters_mut(&mut self) -> Vec<&mut Parameter> {
+        vec![&mut self.weights, &mut self.bias]
+    }
+
+    fn name(&self) -> &str {
+        "CONV2D"
+    }
+
+    fn save(&self) -> Vec<String> {
+        vec![
+            self.input_shape.to_string(),
+            format!(
+                "{} {} {} {}",
+                self.output_shape.channels, self.kernel_size, self.stride, self.padding
+            ),
+            matrix_to_string(&self.weights.value),
+            matrix_to_string(&self.bias.value),
+            format!("{:?}", self.activation.activation_type),
+        ]
+    }
+
+    fn replicate(&self) -> Box<dyn Layer> {
+        Box::new(Conv2D {
+            weights: self.weights.clone(),
+            bias: self.bias.clone(),
+            activation: self.activation.clone(),
+            input_shape: self.input_shape,
+            output_shape: self.output_shape,
+            kernel_size: self.kernel_size,
+            stride: self.stride,
+            padding: self.padding,
+            a: Matrix::zeros(self.a.nrows(), 0),
+            z: Matrix::zeros(self.z.nrows(), 0),
+            columns: Matrix::zeros(self.columns.nrows(), 0),
+        })
+    }
+}
+
+fn random_weights(filters: usize, patch_size: usize) -> Matrix {
+    let distr = Normal::new(0., (2. / patch_size as f32).sqrt()).unwrap();
+
+    let mut rng = thread_rng();
+    Matrix::from_fn(filters, patch_size, |_, _| distr.sample(&mut rng))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        activation::ActivationType,
+        gradient_check::{fill_deterministic, gradient_check},
+        layers::{Conv2D, Layer, Shape},
+        nn::NNBuilder,
+        Matrix,
+    };
+    use nalgebra::dmatrix;
+
+    #[test]
+    fn test_conv2d_forward() {
+        // a single 3x3 image convolved with a 2x2 kernel that sums its patch
+        let mut layer = Conv2D::new(Shape::new(1, 3, 3), 1, 2, 1, 0, ActivationType::ReLu);
+        layer.weights.value = Matrix::from_element(1, 4, 1.);
+
+        let x = Matrix::from_column_slice(9, 1, &[1., 2., 3., 4., 5., 6., 7., 8., 9.]);
+
+        assert_eq!(layer.forward(&x, false), dmatrix![12.; 16.; 24.; 28.]);
+        assert_eq!(layer.output_shape(), Shape::new(1, 2, 2));
+    }
+
+    #[test]
+    fn test_conv2d_gradients() {
+        let shape = Shape::new(2, 5, 5);
+        let x = Matrix::from_fn(shape.len(), 3, |i, j| {
+            ((i * 7 + j * 3) % 11) as f32 / 11. - 0.5
+        });
+        let y = Matrix::from_fn(2, 3, |i, j| ((i + j) % 2) as f32);
+
+        let mut nn = NNBuilder::with_input_shape(shape)
+            .add_conv2d(3, 3, 2, 1, ActivationType::Sigmoid)
+            .add_avg_pool2d(2, 1)
+            .add_flatten()
+            .add_layer(2, ActivationType::Sigmoid)
+            .build();
+
+        fill_deterministic(&mut nn);
+
+        for error in gradient_check(&nn, &x, std::slice::from_ref(&y), 1e-2) {
+            assert!(error < 5e-2, "relative error {error}");
+        }
+    }
+}
@@ -0,0 +1,143 @@
+use std::str::FromStr;
+
+use nalgebra::Dyn;
+use rand::thread_rng;
+use rand_distr::{Distribution, Normal};
+
+use crate::{
+    activation::{Activation, ActivationType},
+    empty_like,
+    layers::Layer,
+    linalg::{gemm, matmul},
+    parameter::Parameter,
+    storage::{matrix_from_string, matrix_to_string},
+    Matrix,
+};
+
+/// Fully connected layer, `activation(weights * input + bias)`
+#[derive(Clone)]
+pub struct Dense {
+    pub(crate) bias: Parameter,
+    pub(crate) weights: Parameter,
+    pub(crate) activation: Activation,
+
+    pub(crate) a: Matrix,
+    pub(crate) z: Matrix,
+}
+
+impl Dense {
+    pub fn new(num_inputs: usize, num_neurons: usize, activation_type: ActivationType) -> Self {
+        Dense {
+            bias: Parameter::new(random_bias(num_neurons), false),
+            weights: Parameter::new(random_weights(num_neurons, num_inputs), true),
+            activation: activation_type.activation(),
+            a: Matrix::zeros(num_neurons, 0),
+            z: Matrix::zeros(num_neurons, 0),
+        }
+    }
+
+    /// Inverse of [Layer::save]
+    pub fn load(lines: &[&str]) -> Self {
+        let bias = matrix_from_string(lines[0]);
+        let weights = matrix_from_string(lines[1]);
+        let activation_type = ActivationType::from_str(lines[2]).unwrap();
+
+        let mut layer = Dense::new(weights.ncols(), weights.nrows(), activation_type);
+        layer.weights.value = weights;
+        layer.bias.value = bias;
+
+        layer
+    }
+}
+
+impl Layer for Dense {
+    fn forward(&mut self, data: &Matrix, _training: bool) -> Matrix {
+        // TODO: find a nicer way to do this
+        if self.a.ncols() != data.ncols() {
+            let shape = (self.bias.value.nrows(), data.ncols());
+            self.a = unsafe { empty_like(shape) };
+            self.z = unsafe { empty_like(shape) };
+        }
+
+        gemm(&mut self.z, &self.weights.value, false, data, false);
+
+        self.z
+            .column_iter_mut()
+            .for_each(|mut col| col += &self.bias.value);
+
+        self.activation.func(&self.z, &mut self.a);
+
+        self.a.clone()
+    }
+
+    fn backward(&mut self, mut delta: Matrix, prev_a: &Matrix) -> Matrix {
+        let mut buffer = unsafe { empty_like(self.z.shape()) };
+        self.activation.derv(&self.z, &mut buffer);
+
+        delta.component_mul_assign(&buffer);
+
+        if self.weights.options.frozen {
+            return matmul(&self.weights.value, true, &delta, false);
+        }
+
+        let dw = matmul(&delta, false, prev_a, true);
+        let db = delta
+            .column_sum()
+            .reshape_generic(Dyn(delta.nrows()), Dyn(1));
+
+        self.weights.accumulate(&dw);
+        self.bias.accumulate(&db);
+
+        matmul(&self.weights.value, true, &delta, false)
+    }
+
+    fn output(&self) -> &Matrix {
+        &self.a
+    }
+
+    fn num_outputs(&self) -> usize {
+        self.bias.value.nrows()
+    }
+
+    fn parameters(&self) -> Vec<&Parameter> {
+        vec![&self.weights, &self.bias]
+    }
+
+    fn parameters_mut(&mut self) -> Vec<&mut Parameter> {
+        vec![&mut self.weights, &mut self.bias]
+    }
+
+    fn name(&self) -> &str {
+        "DENSE"
+    }
+
+    fn save(&self) -> Vec<String> {
+        vec![
+            matrix_to_string(&self.bias.value),
+            matrix_to_string(&self.weights.value),
+            format!("{:?}", self.activation.activation_type),
+        ]
+    }
+
+    fn replicate(&self) -> Box<dyn Layer> {
+        Box::new(Dense {
+            bias: self.bias.clone(),
+            weights: self.weights.clone(),
+            activation: self.activation.clone(),
+            a: Matrix::zeros(self.a.nrows(), 0),
+            z: Matrix::zeros(self.z.nrows(), 0),
+        })
+    }
+}
+
+fn random_weights(num_neurons: usize, num_inputs: usize) -> Matrix {
+    // let distr = Uniform::new(-0.02, 0.02);
+    let distr = Normal::new(0., (2. / num_inputs as f32).sqrt()).unwrap();
+
+    let mut rng = thread_rng();
+    Matrix::from_fn(num_neurons, num_inputs, |_, _| distr.sample(&mut rng))
+}
+
+fn random_bias(num_neurons: usize) -> Matrix {
+    Matrix::zeros(num_neurons, 1)
+}
@@ -0,0 +1,122 @@
+use rand::{rngs::StdRng, thread_rng, Rng, SeedableRng};
+
+use crate::{layers::Layer, parameter::Parameter, Matrix};
+
+/// Zeroes a random fraction of its inputs while training and passes them
+/// through unchanged otherwise
+#[derive(Clone)]
+pub struct Dropout {
+    /// fraction of the inputs that is zeroed during training
+    pub(crate) rate: f32,
+
+    pub(crate) a: Matrix,
+    /// scaled mask of the last training step
+    mask: Option<Matrix>,
+
+    /// the mask of a sample only depends on these and its position in the
+    /// batch, see [Layer::set_batch_offset]
+    seed: u64,
+    steps: u64,
+    offset: usize,
+}
+
+impl Dropout {
+    pub fn new(num_features: usize, rate: f32) -> Self {
+        assert!((0. ..1.).contains(&rate));
+
+        Dropout {
+            rate,
+            a: Matrix::zeros(num_features, 0),
+            mask: None,
+            seed: thread_rng().gen(),
+            steps: 0,
+            offset: 0,
+        }
+    }
+
+    /// Inverse of [Layer::save]
+    pub fn load(lines: &[&str]) -> Self {
+        let num_features = lines[0].parse().unwrap();
+        let rate = lines[1].parse().unwrap();
+
+        Dropout::new(num_features, rate)
+    }
+}
+
+impl Layer for Dropout {
+    fn forward(&mut self, data: &Matrix, training: bool) -> Matrix {
+        self.a = data.clone_owned();
+
+        self.mask = None;
+        if training && self.rate > 0. {
+            // inverted dropout, the kept outputs are scaled up so that
+            // nothing needs to change at inference time
+            let keep = 1. - self.rate;
+            let mut mask = Matrix::zeros(self.a.nrows(), self.a.ncols());
+
+            for (j, mut column) in mask.column_iter_mut().enumerate() {
+                let sample = (self.offset + j) as u64;
+                let mut rng = StdRng::seed_from_u64(self.seed ^ (self.steps << 32) ^ sample);
+
+                for m in column.iter_mut() {
+                    if rng.gen::<f32>() < keep {
+                        *m = 1. / keep;
+                    }
+                }
+            }
+            self.steps += 1;
+
+            self.a.component_mul_assign(&mask);
+            self.mask = Some(mask);
+        }
+
+        self.a.clone()
+    }
+
+    fn backward(&mut self, mut delta: Matrix, _input: &Matrix) -> Matrix {
+        if let Some(mask) = &self.mask {
+            delta.component_mul_assign(mask);
+        }
+
+        delta
+    }
+
+    fn output(&self) -> &Matrix {
+        &self.a
+    }
+
+    fn num_outputs(&self) -> usize {
+        self.a.nrows()
+    }
+
+    fn parameters(&self) -> Vec<&Parameter> {
+        vec![]
+    }
+
+    fn parameters_mut(&mut self) -> Vec<&mut Parameter> {
+        vec![]
+    }
+
+    fn name(&self) -> &str {
+        "DROPOUT"
+    }
+
+    fn save(&self) -> Vec<String> {
+        vec![self.a.nrows().to_string(), self.rate.to_string()]
+    }
+
+    fn replicate(&self) -> Box<dyn Layer> {
+        Box::new(Dropout {
+            rate: self.rate,
+            a: Matrix::zeros(self.a.nrows(), 0),
+            mask: None,
+            seed: self.seed,
+            steps: self.steps,
+            offset: self.offset,
+        })
+    }
+
+    fn set_batch_offset(&mut self, offset: usize) {
+        self.offset = offset;
+    }
+}
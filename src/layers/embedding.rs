@@ -0,0 +1,205 @@
+use rand::thread_rng;
+use rand_distr::{Distribution, Normal};
+
+use crate::{
+    layers::{Layer, Shape},
+    optimizers::Optimizer,
+    parameter::Parameter,
+    storage::{matrix_from_string, matrix_to_string},
+    Matrix,
+};
+
+/// Maps every input, an integer index stored as a float, to a learned vector.
+/// The vectors of the inputs of a sample are stored one after the other, so
+/// the output can be fed to a recurrent layer as a sequence.
+///
+/// Only the vectors that were looked up receive a gradient, they are updated
+/// through [Optimizer::sparse_step]. Weight decay is sparse as well: it only
+/// shrinks the vectors looked up in the batch, even though the reported decay
+/// loss covers the whole table
+#[derive(Clone)]
+pub struct Embedding {
+    /// one column per index, `dim` x `vocab_size`
+    pub(crate) weights: Parameter,
+
+    num_inputs: usize,
+
+    pub(crate) a: Matrix,
+}
+
+impl Embedding {
+    pub fn new(num_inputs: usize, vocab_size: usize, dim: usize) -> Self {
+        Embedding {
+            weights: Parameter::new(random_weights(dim, vocab_size), true),
+            num_inputs,
+            a: Matrix::zeros(num_inputs * dim, 0),
+        }
+    }
+
+    /// Inverse of [Layer::save]
+    pub fn load(lines: &[&str]) -> Self {
+        let num_inputs = lines[0].parse().unwrap();
+        let weights = matrix_from_string(lines[1]);
+
+        let mut layer = Embedding::new(num_inputs, weights.ncols(), weights.nrows());
+        layer.weights.value = weights;
+
+        layer
+    }
+
+    fn dim(&self) -> usize {
+        self.weights.value.nrows()
+    }
+}
+
+impl Layer for Embedding {
+    fn forward(&mut self, data: &Matrix, training: bool) -> Matrix {
+        let (dim, vocab_size) = self.weights.value.shape();
+        self.a = Matrix::zeros(self.num_inputs * dim, data.ncols());
+
+        for (sample, mut out) in data.column_iter().zip(self.a.column_iter_mut()) {
+            for (i, &value) in sample.iter().enumerate() {
+                let index = to_index(value, vocab_size);
+                out.rows_mut(i * dim, dim)
+                    .copy_from(&self.weights.value.column(index));
+
+                if training {
+                    self.weights.touch(index);
+                }
+            }
+        }
+
+        self.a.clone()
+    }
+
+    fn backward(&mut self, delta: Matrix, input: &Matrix) -> Matrix {
+        let (dim, vocab_size) = self.weights.value.shape();
+
+        if !self.weights.options.frozen {
+            for (sample, d) in input.column_iter().zip(delta.column_iter()) {
+                for (i, &value) in sample.iter().enumerate() {
+                    let index = to_index(value, vocab_size);
+                    let mut gradient = self.weights.gradient.column_mut(index);
+                    gradient += d.rows(i * dim, dim);
+                }
+            }
+        }
+
+        // the indices are not differentiable
+        Matrix::zeros(input.nrows(), input.ncols())
+    }
+
+    fn output(&self) -> &Matrix {
+        &self.a
+    }
+
+    fn num_outputs(&self) -> usize {
+        self.num_inputs * self.dim()
+    }
+
+    fn output_shape(&self) -> Shape {
+        Shape::sequence(self.num_inputs, self.dim())
+    }
+
+    fn parameters(&self) -> Vec<&Parameter> {
+        vec![&self.weights]
+    }
+
+    fn parameters_mut(&mut self) -> Vec<&mut Parameter> {
+        vec![&mut self.weights]
+    }
+
+    fn name(&self) -> &str {
+        "EMBEDDING"
+    }
+
+    fn save(&self) -> Vec<String> {
+        vec![
+            self.num_inputs.to_string(),
+            matrix_to_string(&self.weights.value),
+        ]
+    }
+
+    fn replicate(&self) -> Box<dyn Layer> {
+        Box::new(Embedding {
+            weights: self.weights.clone(),
+            num_inputs: self.num_inputs,
+            a: Matrix::zeros(self.a.nrows(), 0),
+        })
+    }
+
+    fn apply_gradients(
+        &mut self,
+        learning_rate: f32,
+        weight_decay: f32,
+        optimizer: &mut dyn Optimizer,
+        step: usize,
+    ) {
+        self.weights
+            .apply_sparse(learning_rate, weight_decay, optimizer, step);
+    }
+}
+
+fn to_index(value: f32, vocab_size: usize) -> usize {
+    assert!(
+        value >= 0. && value.fract() == 0.,
+        "embedding index {value} is not a whole number"
+    );
+    let index = value as usize;
+    assert!(index < vocab_size, "embedding index {value} out of range");
+
+    index
+}
+
+fn random_weights(dim: usize, vocab_size: usize) -> Matrix {
+    let distr = Normal::new(0., 1. / (dim as f32).sqrt()).unwrap();
+
+    let mut rng = thread_rng();
+    Matrix::from_fn(dim, vocab_size, |_, _| distr.sample(&mut rng))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        layers::{Embedding, Layer},
+        optimizers::{adam_optimizer::AdamOptimizer, default_optimizer::DefaultOptimizer},
+        Matrix,
+    };
+    use nalgebra::dmatrix;
+
+    #[test]
+    fn test_embedding_sparse_update() {
+        let mut layer = Embedding::new(2, 5, 3);
+        let mut optimizer = AdamOptimizer::default();
+        layer.register(&mut optimizer);
+
+        let before = layer.weights.value.clone();
+        let x = dmatrix![
+            1., 3.;
+            3., 3.;
+        ];
+
+        let out = layer.forward(&x, true);
+        assert_eq!(out.column(0).rows(0, 3), before.column(1));
+        assert_eq!(out.column(0).rows(3, 3), before.column(3));
+
+        layer.backward(Matrix::from_element(6, 2, 1.), &x);
+        assert_eq!(layer.weights.gradient.column(3).sum(), 9.);
+
+        // adam normalizes the step, compare decay with plain gradient descent
+        let mut decay = layer.clone();
+        let mut no_decay = layer.clone();
+        decay.apply_gradients(0.1, 0.01, &mut DefaultOptimizer, 0);
+        no_decay.apply_gradients(0.1, 0., &mut DefaultOptimizer, 0);
+        layer.apply_gradients(0.1, 0.01, &mut optimizer, 0);
+
+        for c in 0..5 {
+            let changed = layer.weights.value.column(c) != before.column(c);
+            assert_eq!(changed, c == 1 || c == 3, "column {c}");
+            // decay only reaches the columns that were looked up
+            let decayed = decay.weights.value.column(c) != no_decay.weights.value.column(c);
+            assert_eq!(decayed, c == 1 || c == 3, "column {c}");
+        }
+        assert_eq!(layer.weights.gradient.amax(), 0.);
+    }
+}
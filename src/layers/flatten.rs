@@ -0,0 +1,66 @@
+use crate::{
+    layers::{Layer, Shape},
+    parameter::Parameter,
+    Matrix,
+};
+
+/// Marks the end of the image layers, the data is already stored as one
+/// column per sample so only the [Shape] changes
+#[derive(Clone)]
+pub struct Flatten {
+    pub(crate) a: Matrix,
+}
+
+impl Flatten {
+    pub fn new(input_shape: Shape) -> Self {
+        Flatten {
+            a: Matrix::zeros(input_shape.len(), 0),
+        }
+    }
+
+    /// Inverse of [Layer::save]
+    pub fn load(lines: &[&str]) -> Self {
+        Flatten::new(Shape::flat(lines[0].parse().unwrap()))
+    }
+}
+
+impl Layer for Flatten {
+    fn forward(&mut self, data: &Matrix, _training: bool) -> Matrix {
+        self.a = data.clone_owned();
+        self.a.clone()
+    }
+
+    fn backward(&mut self, delta: Matrix, _input: &Matrix) -> Matrix {
+        delta
+    }
+
+    fn output(&self) -> &Matrix {
+        &self.a
+    }
+
+    fn num_outputs(&self) -> usize {
+        self.a.nrows()
+    }
+
+    fn parameters(&self) -> Vec<&Parameter> {
+        vec![]
+    }
+
+    fn parameters_mut(&mut self) -> Vec<&mut Parameter> {
+        vec![]
+    }
+
+    fn name(&self) -> &str {
+        "FLATTEN"
+    }
+
+    fn save(&self) -> Vec<String> {
+        vec![self.a.nrows().to_string()]
+    }
+
+    fn replicate(&self) -> Box<dyn Layer> {
+        Box::new(Flatten {
+            a: Matrix::zeros(self.a.nrows(), 0),
+        })
+    }
+}
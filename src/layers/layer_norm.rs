@@ -0,0 +1,191 @@
+use nalgebra::Dyn;
+
+use crate::{
+    layers::Layer,
+    parameter::Parameter,
+    storage::{matrix_from_string, matrix_to_string},
+    Matrix,
+};
+
+const EPSILON: f32 = 1e-5;
+
+/// Normalizes every sample (column) over its features, then scales and
+/// shifts it by the learnable `gamma` and `beta`. Unlike [super::batch_norm::BatchNorm]
+/// this does not depend on the other samples in the batch, so training and
+/// inference behave the same, even for a batch size of one
+#[derive(Clone)]
+pub struct LayerNorm {
+    pub(crate) gamma: Parameter,
+    pub(crate) beta: Parameter,
+
+    pub(crate) a: Matrix,
+    x_hat: Matrix,
+    /// one entry per sample
+    inv_std: Vec<f32>,
+}
+
+impl LayerNorm {
+    pub fn new(num_features: usize) -> Self {
+        LayerNorm {
+            gamma: Parameter::new(Matrix::from_element(num_features, 1, 1.), false),
+            beta: Parameter::new(Matrix::zeros(num_features, 1), false),
+            a: Matrix::zeros(num_features, 0),
+            x_hat: Matrix::zeros(num_features, 0),
+            inv_std: vec![],
+        }
+    }
+
+    /// Inverse of [Layer::save]
+    pub fn load(lines: &[&str]) -> Self {
+        let gamma = matrix_from_string(lines[0]);
+
+        let mut layer = LayerNorm::new(gamma.nrows());
+        layer.gamma.value = gamma;
+        layer.beta.value = matrix_from_string(lines[1]);
+
+        layer
+    }
+}
+
+impl Layer for LayerNorm {
+    fn forward(&mut self, data: &Matrix, _training: bool) -> Matrix {
+        self.x_hat = data.clone_owned();
+        self.inv_std.clear();
+
+        for mut col in self.x_hat.column_iter_mut() {
+            let mean = col.mean();
+            let inv_std = 1. / (col.variance() + EPSILON).sqrt();
+
+            col.add_scalar_mut(-mean);
+            col *= inv_std;
+            self.inv_std.push(inv_std);
+        }
+
+        self.a = self.x_hat.clone();
+        for mut col in self.a.column_iter_mut() {
+            col.component_mul_assign(&self.gamma.value);
+            col += &self.beta.value;
+        }
+
+        self.a.clone()
+    }
+
+    fn backward(&mut self, delta: Matrix, _input: &Matrix) -> Matrix {
+        let nrows = delta.nrows();
+
+        if !self.gamma.options.frozen {
+            let dgamma = delta
+                .component_mul(&self.x_hat)
+                .column_sum()
+                .reshape_generic(Dyn(nrows), Dyn(1));
+            let dbeta = delta.column_sum().reshape_generic(Dyn(nrows), Dyn(1));
+
+            self.gamma.accumulate(&dgamma);
+            self.beta.accumulate(&dbeta);
+        }
+
+        let n = nrows as f32;
+        let mut dx = delta;
+
+        for ((mut col, x_hat), inv_std) in dx
+            .column_iter_mut()
+            .zip(self.x_hat.column_iter())
+            .zip(&self.inv_std)
+        {
+            col.component_mul_assign(&self.gamma.value);
+
+            let sum = col.sum();
+            let sum_x_hat = col.dot(&x_hat);
+
+            for i in 0..nrows {
+                col[i] = inv_std / n * (n * col[i] - sum - x_hat[i] * sum_x_hat);
+            }
+        }
+
+        dx
+    }
+
+    fn output(&self) -> &Matrix {
+        &self.a
+    }
+
+    fn num_outputs(&self) -> usize {
+        self.beta.value.nrows()
+    }
+
+    fn parameters(&self) -> Vec<&Parameter> {
+        vec![&self.gamma, &self.beta]
+    }
+
+    fn parameters_mut(&mut self) -> Vec<&mut Parameter> {
+        vec![&mut self.gamma, &mut self.beta]
+    }
+
+    fn name(&self) -> &str {
+        "LAYER_NORM"
+    }
+
+    fn save(&self) -> Vec<String> {
+        vec![
+            matrix_to_string(&self.gamma.value),
+            matrix_to_string(&self.beta.value),
+        ]
+    }
+
+    fn replicate(&self) -> Box<dyn Layer> {
+        Box::new(LayerNorm {
+            gamma: self.gamma.clone(),
+            beta: self.beta.clone(),
+            a: Matrix::zeros(self.a.nrows(), 0),
+            x_hat: Matrix::zeros(self.x_hat.nrows(), 0),
+            inv_std: vec![],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        layers::{Layer, LayerNorm},
+        Matrix,
+    };
+    use nalgebra::dmatrix;
+
+    /// compares `backward` with central differences of the weighted sum of
+    /// the normalized outputs
+    #[test]
+    fn test_layer_norm_backprop() {
+        let x = dmatrix![
+            0.3, -0.8;
+            -0.4, 0.6;
+            1.1, 0.2;
+        ];
+        let weights = dmatrix![
+            0.2, -0.5;
+            0.9, 0.3;
+            -0.4, 0.8;
+        ];
+        let eps = 1e-2;
+
+        let mut layer = LayerNorm::new(3);
+        layer.gamma.value = dmatrix![1.5; 0.5; -0.7];
+        layer.beta.value = dmatrix![0.1; -0.2; 0.3];
+
+        let loss = |layer: &mut LayerNorm, x: &Matrix| -> f32 {
+            layer.forward(x, true).component_mul(&weights).sum()
+        };
+
+        loss(&mut layer, &x);
+        let dx = layer.backward(weights.clone(), &x);
+
+        for i in 0..x.len() {
+            let mut x_plus = x.clone();
+            x_plus[i] += eps;
+            let mut x_minus = x.clone();
+            x_minus[i] -= eps;
+
+            let numeric = (loss(&mut layer, &x_plus) - loss(&mut layer, &x_minus)) / (2. * eps);
+            assert!((numeric - dx[i]).abs() < 1e-2, "{numeric} {}", dx[i]);
+        }
+    }
+}
@@ -0,0 +1,175 @@
+use crate::{
+    layers::{Layer, Shape},
+    parameter::Parameter,
+    Matrix,
+};
+
+/// Sums the outputs of several nodes of the same shape, eg. for a residual
+/// connection. Like every node with more than one input it receives them
+/// stacked on top of each other, see [crate::graph::GraphBuilder]
+#[derive(Clone)]
+pub struct Add {
+    num_inputs: usize,
+    shape: Shape,
+
+    pub(crate) a: Matrix,
+}
+
+impl Add {
+    pub fn new(num_inputs: usize, shape: Shape) -> Self {
+        Add {
+            num_inputs,
+            shape,
+            a: Matrix::zeros(shape.len(), 0),
+        }
+    }
+
+    /// Inverse of [Layer::save]
+    pub fn load(lines: &[&str]) -> Self {
+        Add::new(lines[0].parse().unwrap(), Shape::from_string(lines[1]))
+    }
+}
+
+impl Layer for Add {
+    fn forward(&mut self, data: &Matrix, _training: bool) -> Matrix {
+        let len = self.shape.len();
+
+        self.a = data.rows(0, len).clone_owned();
+        for i in 1..self.num_inputs {
+            self.a += data.rows(i * len, len);
+        }
+
+        self.a.clone()
+    }
+
+    fn backward(&mut self, delta: Matrix, _input: &Matrix) -> Matrix {
+        let len = self.shape.len();
+        let mut dx = Matrix::zeros(self.num_inputs * len, delta.ncols());
+
+        for i in 0..self.num_inputs {
+            dx.rows_mut(i * len, len).copy_from(&delta);
+        }
+
+        dx
+    }
+
+    fn output(&self) -> &Matrix {
+        &self.a
+    }
+
+    fn num_outputs(&self) -> usize {
+        self.shape.len()
+    }
+
+    fn output_shape(&self) -> Shape {
+        self.shape
+    }
+
+    fn parameters(&self) -> Vec<&Parameter> {
+        vec![]
+    }
+
+    fn parameters_mut(&mut self) -> Vec<&mut Parameter> {
+        vec![]
+    }
+
+    fn name(&self) -> &str {
+        "ADD"
+    }
+
+    fn save(&self) -> Vec<String> {
+        vec![self.num_inputs.to_string(), self.shape.to_string()]
+    }
+
+    fn replicate(&self) -> Box<dyn Layer> {
+        Box::new(Add {
+            num_inputs: self.num_inputs,
+            shape: self.shape,
+            a: Matrix::zeros(self.a.nrows(), 0),
+        })
+    }
+}
+
+/// Passes the stacked outputs of several nodes on as one, images of the same
+/// size are joined along their channels
+#[derive(Clone)]
+pub struct Concat {
+    shape: Shape,
+
+    pub(crate) a: Matrix,
+}
+
+impl Concat {
+    pub fn new(shape: Shape) -> Self {
+        Concat {
+            shape,
+            a: Matrix::zeros(shape.len(), 0),
+        }
+    }
+
+    /// The shape of the joined output of nodes with the given shapes
+    pub fn output_shape_of(shapes: &[Shape]) -> Shape {
+        let first = shapes[0];
+        let same_size = shapes
+            .iter()
+            .all(|s| s.height == first.height && s.width == first.width);
+
+        if same_size {
+            let channels = shapes.iter().map(|s| s.channels).sum();
+            Shape::new(channels, first.height, first.width)
+        } else {
+            Shape::flat(shapes.iter().map(Shape::len).sum())
+        }
+    }
+
+    /// Inverse of [Layer::save]
+    pub fn load(lines: &[&str]) -> Self {
+        Concat::new(Shape::from_string(lines[0]))
+    }
+}
+
+impl Layer for Concat {
+    fn forward(&mut self, data: &Matrix, _training: bool) -> Matrix {
+        self.a = data.clone_owned();
+        self.a.clone()
+    }
+
+    fn backward(&mut self, delta: Matrix, _input: &Matrix) -> Matrix {
+        delta
+    }
+
+    fn output(&self) -> &Matrix {
+        &self.a
+    }
+
+    fn num_outputs(&self) -> usize {
+        self.shape.len()
+    }
+
+    fn output_shape(&self) -> Shape {
+        self.shape
+    }
+
+    fn parameters(&self) -> Vec<&Parameter> {
+        vec![]
+    }
+
+    fn parameters_mut(&mut self) -> Vec<&mut Parameter> {
+        vec![]
+    }
+
+    fn name(&self) -> &str {
+        "CONCAT"
+    }
+
+    fn save(&self) -> Vec<String> {
+        vec![self.shape.to_string()]
+    }
+
+    fn replicate(&self) -> Box<dyn Layer> {
+        Box::new(Concat {
+            shape: self.shape,
+            a: Matrix::zeros(self.a.nrows(), 0),
+        })
+    }
+}
@@ -0,0 +1,159 @@
+pub use attention::MultiHeadAttention;
+pub use batch_norm::BatchNorm;
+pub use conv2d::Conv2D;
+pub use dense::Dense;
+pub use dropout::Dropout;
+pub use embedding::Embedding;
+pub use flatten::Flatten;
+pub use layer_norm::LayerNorm;
+pub use merge::{Add, Concat};
+pub use pooling::{AvgPool2D, MaxPool2D};
+pub use recurrent::{CellType, Recurrent, RecurrentOptions};
+
+use std::fmt::Display;
+
+use crate::{optimizers::Optimizer, parameter::Parameter, Matrix};
+
+mod attention;
+mod batch_norm;
+mod conv2d;
+mod dense;
+mod dropout;
+mod embedding;
+mod flatten;
+mod layer_norm;
+mod merge;
+mod pooling;
+mod recurrent;
+
+/// Describes how the rows of a sample column are laid out for layers that
+/// work on images: channel after channel, each one stored row by row
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Shape {
+    pub channels: usize,
+    pub height: usize,
+    pub width: usize,
+}
+
+impl Shape {
+    pub fn new(channels: usize, height: usize, width: usize) -> Self {
+        Shape {
+            channels,
+            height,
+            width,
+        }
+    }
+
+    /// A plain vector of `len` features
+    pub fn flat(len: usize) -> Self {
+        Shape::new(len, 1, 1)
+    }
+
+    /// `timesteps` vectors of `num_features` each, stored one after the
+    /// other, see [crate::utils::stack_sequence]
+    pub fn sequence(timesteps: usize, num_features: usize) -> Self {
+        Shape::new(1, timesteps, num_features)
+    }
+
+    /// Number of rows a sample with this shape takes up
+    pub fn len(&self) -> usize {
+        self.channels * self.height * self.width
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Row of the value at `(channel, y, x)`
+    pub(crate) fn index(&self, channel: usize, y: usize, x: usize) -> usize {
+        (channel * self.height + y) * self.width + x
+    }
+
+    pub(crate) fn from_string(s: &str) -> Self {
+        let mut parts = s.split(' ').map(|x| x.parse::<usize>().unwrap());
+
+        Shape::new(
+            parts.next().unwrap(),
+            parts.next().unwrap(),
+            parts.next().unwrap(),
+        )
+    }
+}
+
+impl Display for Shape {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} {}", self.channels, self.height, self.width)
+    }
+}
+
+/// A step of the network. Every column of the matrices passed around is one
+/// sample of the batch.
+pub trait Layer: Send {
+    /// Compute the output for a batch, `training` is set while the network is
+    /// being trained and enables eg. dropout and batch statistics
+    fn forward(&mut self, input: &Matrix, training: bool) -> Matrix;
+
+    /// Given the derivative of the loss with respect to the output of the last
+    /// forward pass, add the gradients of this batch to the parameters and
+    /// return the derivative with respect to `input`
+    fn backward(&mut self, delta: Matrix, input: &Matrix) -> Matrix;
+
+    /// Output of the last forward pass, the input of the next layer
+    fn output(&self) -> &Matrix;
+
+    fn num_outputs(&self) -> usize;
+
+    /// Layers that produce images override this, so the next layer knows
+    /// how to interpret their output
+    fn output_shape(&self) -> Shape {
+        Shape::flat(self.num_outputs())
+    }
+
+    fn parameters(&self) -> Vec<&Parameter>;
+
+    fn parameters_mut(&mut self) -> Vec<&mut Parameter>;
+
+    /// Identifies the layer type in storage
+    fn name(&self) -> &str;
+
+    /// Lines written to storage, they are passed back to the loader
+    /// registered for [Layer::name] when reading the network
+    fn save(&self) -> Vec<String>;
+
+    /// A copy of the layer with all of its state, eg. running statistics, to
+    /// run on another thread, see
+    /// [NNOptions::num_threads](crate::nn::NNOptions::num_threads). Replicas
+    /// are made for every batch, so the values cached by the last forward
+    /// pass should be left out
+    fn replicate(&self) -> Box<dyn Layer>;
+
+    /// Whether the output of a sample depends on the other samples of the
+    /// batch while training, the batches of networks with such layers are
+    /// not split over threads
+    fn mixes_samples(&self) -> bool {
+        false
+    }
+
+    /// Position in the whole batch of the first column passed to
+    /// [Layer::forward], set when a batch is split over threads so that
+    /// random layers draw the same values for every sample either way
+    fn set_batch_offset(&mut self, _offset: usize) {}
+
+    fn register(&mut self, optimizer: &mut dyn Optimizer) {
+        for parameter in self.parameters_mut() {
+            parameter.register(optimizer);
+        }
+    }
+
+    fn apply_gradients(
+        &mut self,
+        learning_rate: f32,
+        weight_decay: f32,
+        optimizer: &mut dyn Optimizer,
+        step: usize,
+    ) {
+        for parameter in self.parameters_mut() {
+            parameter.apply(learning_rate, weight_decay, optimizer, step);
+        }
+    }
+}
@@ -0,0 +1,310 @@
+use crate::{
+    layers::{Layer, Shape},
+    parameter::Parameter,
+    Matrix,
+};
+
+/// Window size and stride shared by the pooling layers
+#[derive(Clone)]
+struct Pool {
+    input_shape: Shape,
+    output_shape: Shape,
+    size: usize,
+    stride: usize,
+}
+
+impl Pool {
+    fn new(input_shape: Shape, size: usize, stride: usize) -> Self {
+        assert!(stride > 0);
+        assert!(
+            input_shape.height >= size && input_shape.width >= size,
+            "pooling window does not fit in the input"
+        );
+
+        let output_shape = Shape::new(
+            input_shape.channels,
+            (input_shape.height - size) / stride + 1,
+            (input_shape.width - size) / stride + 1,
+        );
+
+        Pool {
+            input_shape,
+            output_shape,
+            size,
+            stride,
+        }
+    }
+
+    fn load(lines: &[&str]) -> Self {
+        let input_shape = Shape::from_string(lines[0]);
+        let size = lines[1].parse().unwrap();
+        let stride = lines[2].parse().unwrap();
+
+        Pool::new(input_shape, size, stride)
+    }
+
+    fn save(&self) -> Vec<String> {
+        vec![
+            self.input_shape.to_string(),
+            self.size.to_string(),
+            self.stride.to_string(),
+        ]
+    }
+
+    /// Call `f` with the output row and the input rows of its window, for
+    /// every output value of a sample
+    fn for_each_window(&self, mut f: impl FnMut(usize, &mut dyn Iterator<Item = usize>)) {
+        for c in 0..self.output_shape.channels {
+            for oy in 0..self.output_shape.height {
+                for ox in 0..self.output_shape.width {
+                    let (y, x) = (oy * self.stride, ox * self.stride);
+                    let mut window = (0..self.size).flat_map(|ky| {
+                        (0..self.size).map(move |kx| self.input_shape.index(c, y + ky, x + kx))
+                    });
+
+                    f(self.output_shape.index(c, oy, ox), &mut window);
+                }
+            }
+        }
+    }
+}
+
+/// Keeps the largest value of every window, for each channel separately
+#[derive(Clone)]
+pub struct MaxPool2D {
+    pool: Pool,
+
+    pub(crate) a: Matrix,
+    /// input row each output value was taken from, per sample
+    argmax: Vec<Vec<usize>>,
+}
+
+impl MaxPool2D {
+    pub fn new(input_shape: Shape, size: usize, stride: usize) -> Self {
+        let pool = Pool::new(input_shape, size, stride);
+
+        MaxPool2D {
+            a: Matrix::zeros(pool.output_shape.len(), 0),
+            argmax: vec![],
+            pool,
+        }
+    }
+
+    /// Inverse of [Layer::save]
+    pub fn load(lines: &[&str]) -> Self {
+        let pool = Pool::load(lines);
+
+        MaxPool2D {
+            a: Matrix::zeros(pool.output_shape.len(), 0),
+            argmax: vec![],
+            pool,
+        }
+    }
+}
+
+impl Layer for MaxPool2D {
+    fn forward(&mut self, data: &Matrix, _training: bool) -> Matrix {
+        self.a = Matrix::zeros(self.pool.output_shape.len(), data.ncols());
+        self.argmax = vec![vec![0; self.pool.output_shape.len()]; data.ncols()];
+
+        for ((sample, mut out), argmax) in data
+            .column_iter()
+            .zip(self.a.column_iter_mut())
+            .zip(&mut self.argmax)
+        {
+            self.pool.for_each_window(|o, window| {
+                let i = window
+                    .max_by(|&i, &j| sample[i].total_cmp(&sample[j]))
+                    .unwrap();
+
+                out[o] = sample[i];
+                argmax[o] = i;
+            });
+        }
+
+        self.a.clone()
+    }
+
+    fn backward(&mut self, delta: Matrix, _input: &Matrix) -> Matrix {
+        let mut dx = Matrix::zeros(self.pool.input_shape.len(), delta.ncols());
+
+        for ((d, mut dx), argmax) in delta
+            .column_iter()
+            .zip(dx.column_iter_mut())
+            .zip(&self.argmax)
+        {
+            for (o, &i) in argmax.iter().enumerate() {
+                dx[i] += d[o];
+            }
+        }
+
+        dx
+    }
+
+    fn output(&self) -> &Matrix {
+        &self.a
+    }
+
+    fn num_outputs(&self) -> usize {
+        self.pool.output_shape.len()
+    }
+
+    fn output_shape(&self) -> Shape {
+        self.pool.output_shape
+    }
+
+    fn parameters(&self) -> Vec<&Parameter> {
+        vec![]
+    }
+
+    fn parameters_mut(&mut self) -> Vec<&mut Parameter> {
+        vec![]
+    }
+
+    fn name(&self) -> &str {
+        "MAX_POOL2D"
+    }
+
+    fn save(&self) -> Vec<String> {
+        self.pool.save()
+    }
+
+    fn replicate(&self) -> Box<dyn Layer> {
+        Box::new(MaxPool2D {
+            pool: self.pool.clone(),
+            a: Matrix::zeros(self.a.nrows(), 0),
+            argmax: vec![],
+        })
+    }
+}
+
+/// Averages every window, for each channel separately
+#[derive(Clone)]
+pub struct AvgPool2D {
+    pool: Pool,
+
+    pub(crate) a: Matrix,
+}
+
+impl AvgPool2D {
+    pub fn new(input_shape: Shape, size: usize, stride: usize) -> Self {
+        let pool = Pool::new(input_shape, size, stride);
+
+        AvgPool2D {
+            a: Matrix::zeros(pool.output_shape.len(), 0),
+            pool,
+        }
+    }
+
+    /// Inverse of [Layer::save]
+    pub fn load(lines: &[&str]) -> Self {
+        let pool = Pool::load(lines);
+
+        AvgPool2D {
+            a: Matrix::zeros(pool.output_shape.len(), 0),
+            pool,
+        }
+    }
+}
+
+impl Layer for AvgPool2D {
+    fn forward(&mut self, data: &Matrix, _training: bool) -> Matrix {
+        let scale = 1. / (self.pool.size * self.pool.size) as f32;
+        self.a = Matrix::zeros(self.pool.output_shape.len(), data.ncols());
+
+        for (sample, mut out) in data.column_iter().zip(self.a.column_iter_mut()) {
+            self.pool.for_each_window(|o, window| {
+                out[o] = window.map(|i| sample[i]).sum::<f32>() * scale;
+            });
+        }
+
+        self.a.clone()
+    }
+
+    fn backward(&mut self, delta: Matrix, _input: &Matrix) -> Matrix {
+        let scale = 1. / (self.pool.size * self.pool.size) as f32;
+        let mut dx = Matrix::zeros(self.pool.input_shape.len(), delta.ncols());
+
+        for (d, mut dx) in delta.column_iter().zip(dx.column_iter_mut()) {
+            self.pool.for_each_window(|o, window| {
+                for i in window {
+                    dx[i] += d[o] * scale;
+                }
+            });
+        }
+
+        dx
+    }
+
+    fn output(&self) -> &Matrix {
+        &self.a
+    }
+
+    fn num_outputs(&self) -> usize {
+        self.pool.output_shape.len()
+    }
+
+    fn output_shape(&self) -> Shape {
+        self.pool.output_shape
+    }
+
+    fn parameters(&self) -> Vec<&Parameter> {
+        vec![]
+    }
+
+    fn parameters_mut(&mut self) -> Vec<&mut Parameter> {
+        vec![]
+    }
+
+    fn name(&self) -> &str {
+        "AVG_POOL2D"
+    }
+
+    fn save(&self) -> Vec<String> {
+        self.pool.save()
+    }
+
+    fn replicate(&self) -> Box<dyn Layer> {
+        Box::new(AvgPool2D {
+            pool: self.pool.clone(),
+            a: Matrix::zeros(self.a.nrows(), 0),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        layers::{AvgPool2D, Layer, MaxPool2D, Shape},
+        Matrix,
+    };
+    use nalgebra::dmatrix;
+
+    #[test]
+    fn test_pooling() {
+        // one 4x4 channel, pooled with 2x2 windows
+        let x = Matrix::from_column_slice(
+            16,
+            1,
+            &[
+                1., 2., 5., 6., //
+                3., 4., 8., 7., //
+                0., 1., 2., 2., //
+                9., 1., 2., 2., //
+            ],
+        );
+
+        let mut max_pool = MaxPool2D::new(Shape::new(1, 4, 4), 2, 2);
+        assert_eq!(max_pool.forward(&x, false), dmatrix![4.; 8.; 9.; 2.]);
+
+        let dx = max_pool.backward(dmatrix![1.; 2.; 3.; 4.], &x);
+        assert_eq!(dx[5], 1.);
+        assert_eq!(dx[6], 2.);
+        assert_eq!(dx[12], 3.);
+        assert_eq!(dx.sum(), 10.);
+
+        let mut avg_pool = AvgPool2D::new(Shape::new(1, 4, 4), 2, 2);
+        assert_eq!(avg_pool.forward(&x, false), dmatrix![2.5; 6.5; 2.75; 2.]);
+        assert_eq!(avg_pool.output_shape(), Shape::new(1, 2, 2));
+    }
+}
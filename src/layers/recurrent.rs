@@ -0,0 +1,509 @@
+use std::str::FromStr;
+
+use nalgebra::Dyn;
+use rand::thread_rng;
+use rand_distr::{Distribution, Normal};
+
+use crate::{
+    layers::{Layer, Shape},
+    parameter::Parameter,
+    storage::{matrix_from_string, matrix_to_string},
+    utils::stack_sequence,
+    Matrix,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CellType {
+    /// `h = tanh(W x + U h + b)`
+    SimpleRNN,
+    LSTM,
+    GRU,
+}
+
+impl CellType {
+    /// Number of gates, the weights of all gates are stacked on top of each
+    /// other in one matrix
+    fn num_gates(&self) -> usize {
+        match self {
+            CellType::SimpleRNN => 1,
+            CellType::LSTM => 4,
+            CellType::GRU => 3,
+        }
+    }
+}
+
+impl FromStr for CellType {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "SimpleRNN" => Ok(CellType::SimpleRNN),
+            "LSTM" => Ok(CellType::LSTM),
+            "GRU" => Ok(CellType::GRU),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct RecurrentOptions {
+    /// Output the hidden state of every timestep instead of only the last
+    /// one, needed to stack recurrent layers
+    pub return_sequences: bool,
+    /// Truncated backpropagation through time: the sequence is split into
+    /// chunks of this many timesteps, counted from the end, and gradients
+    /// do not flow from one chunk into the previous one
+    pub bptt_steps: Option<usize>,
+}
+
+/// Runs a [CellType] over a sequence. The timesteps of a sample are stored
+/// one after the other in its column, as described by [Shape::sequence]
+#[derive(Clone)]
+pub struct Recurrent {
+    pub(crate) cell_type: CellType,
+    pub(crate) options: RecurrentOptions,
+
+    /// input weights of all gates, `num_gates * num_units` x `num_features`
+    pub(crate) w: Parameter,
+    /// recurrent weights of all gates, `num_gates * num_units` x `num_units`
+    pub(crate) u: Parameter,
+    pub(crate) bias: Parameter,
+
+    timesteps: usize,
+    num_features: usize,
+    num_units: usize,
+
+    pub(crate) a: Matrix,
+    /// hidden states of the last forward pass, starting with the zero state
+    hs: Vec<Matrix>,
+    /// LSTM cell states, starting with the zero state
+    cs: Vec<Matrix>,
+    /// activated gates of every timestep
+    gates: Vec<Matrix>,
+}
+
+impl Recurrent {
+    pub fn new(
+        cell_type: CellType,
+        input_shape: Shape,
+        num_units: usize,
+        options: RecurrentOptions,
+    ) -> Self {
+        let timesteps = input_shape.height;
+        let num_features = input_shape.width;
+        assert_eq!(
+            input_shape.channels, 1,
+            "recurrent layers expect a sequence, see Shape::sequence"
+        );
+
+        let rows = cell_type.num_gates() * num_units;
+        let mut bias = Matrix::zeros(rows, 1);
+        if cell_type == CellType::LSTM {
+            // start out remembering, the forget gate is the second one
+            bias.rows_mut(num_units, num_units).fill(1.);
+        }
+
+        let num_outputs = if options.return_sequences {
+            timesteps * num_units
+        } else {
+            num_units
+        };
+
+        Recurrent {
+            cell_type,
+            options,
+            w: Parameter::new(random_weights(rows, num_features, num_units), true),
+            u: Parameter::new(random_weights(rows, num_units, num_units), true),
+            bias: Parameter::new(bias, false),
+            timesteps,
+            num_features,
+            num_units,
+            a: Matrix::zeros(num_outputs, 0),
+            hs: vec![],
+            cs: vec![],
+            gates: vec![],
+        }
+    }
+
+    /// Inverse of [Layer::save]
+    pub fn load(lines: &[&str]) -> Self {
+        let cell_type = CellType::from_str(lines[0]).unwrap();
+        let config = lines[1]
+            .split(' ')
+            .map(|x| x.parse::<usize>().unwrap())
+            .collect::<Vec<_>>();
+        let options = RecurrentOptions {
+            return_sequences: config[3] == 1,
+            bptt_steps: Some(config[4]).filter(|&steps| steps > 0),
+        };
+
+        let mut layer = Recurrent::new(
+            cell_type,
+            Shape::sequence(config[0], config[1]),
+            config[2],
+            options,
+        );
+        layer.w.value = matrix_from_string(lines[2]);
+        layer.u.value = matrix_from_string(lines[3]);
+        layer.bias.value = matrix_from_string(lines[4]);
+
+        layer
+    }
+
+    /// Rows `gate * num_units..` of a stacked matrix
+    fn gate(&self, m: &Matrix, gate: usize) -> Matrix {
+        m.rows(gate * self.num_units, self.num_units).clone_owned()
+    }
+
+    fn step(&mut self, x: &Matrix) {
+        let h_prev = self.hs.last().unwrap();
+        let n = self.num_units;
+
+        let mut g = &self.w.value * x;
+        for mut col in g.column_iter_mut() {
+            col += &self.bias.value;
+        }
+
+        match self.cell_type {
+            CellType::SimpleRNN => {
+                g += &self.u.value * h_prev;
+                g.apply(|x| *x = x.tanh());
+
+                self.hs.push(g.clone());
+            }
+            CellType::LSTM => {
+                g += &self.u.value * h_prev;
+                g.rows_mut(0, 3 * n).apply(|x| *x = sigmoid(*x));
+                g.rows_mut(3 * n, n).apply(|x| *x = x.tanh());
+
+                let (i, f, o, c_tilde) = (
+                    self.gate(&g, 0),
+                    self.gate(&g, 1),
+                    self.gate(&g, 2),
+                    self.gate(&g, 3),
+                );
+                let c = f.component_mul(self.cs.last().unwrap()) + i.component_mul(&c_tilde);
+                let h = o.component_mul(&c.map(|x| x.tanh()));
+
+                self.cs.push(c);
+                self.hs.push(h);
+            }
+            CellType::GRU => {
+                // update and reset gate
+                let mut zr = g.rows(0, 2 * n) + self.u.value.rows(0, 2 * n) * h_prev;
+                zr.apply(|x| *x = sigmoid(*x));
+                g.rows_mut(0, 2 * n).copy_from(&zr);
+
+                let (z, r) = (self.gate(&g, 0), self.gate(&g, 1));
+                let mut candidate =
+                    g.rows(2 * n, n) + self.u.value.rows(2 * n, n) * r.component_mul(h_prev);
+                candidate.apply(|x| *x = x.tanh());
+                g.rows_mut(2 * n, n).copy_from(&candidate);
+
+                let h = z.map(|z| 1. - z).component_mul(&candidate) + z.component_mul(h_prev);
+                self.hs.push(h);
+            }
+        }
+
+        self.gates.push(g);
+    }
+
+    /// Derivative with respect to the pre-activation gates of timestep `t`,
+    /// given the derivative of the loss with respect to its hidden state.
+    /// `dc` carries the derivative of the LSTM cell state between calls
+    fn backward_step(&self, t: usize, dh: &Matrix, dc: &mut Matrix) -> Matrix {
+        let g = &self.gates[t];
+        let h_prev = &self.hs[t];
+        let n = self.num_units;
+
+        let mut dg = Matrix::zeros(g.nrows(), g.ncols());
+
+        match self.cell_type {
+            CellType::SimpleRNN => {
+                dg.zip_zip_apply(dh, g, |d, dh, h| *d = dh * (1. - h * h));
+            }
+            CellType::LSTM => {
+                let (i, f, o, c_tilde) = (
+                    self.gate(g, 0),
+                    self.gate(g, 1),
+                    self.gate(g, 2),
+                    self.gate(g, 3),
+                );
+                let tanh_c = self.cs[t + 1].map(|x| x.tanh());
+
+                *dc += dh
+                    .component_mul(&o)
+                    .component_mul(&tanh_c.map(|x| 1. - x * x));
+
+                let di = dc.component_mul(&c_tilde);
+                let df = dc.component_mul(&self.cs[t]);
+                let d_o = dh.component_mul(&tanh_c);
+                let dc_tilde = dc.component_mul(&i);
+
+                dg.rows_mut(0, n)
+                    .copy_from(&di.component_mul(&i.map(|x| x * (1. - x))));
+                dg.rows_mut(n, n)
+                    .copy_from(&df.component_mul(&f.map(|x| x * (1. - x))));
+                dg.rows_mut(2 * n, n)
+                    .copy_from(&d_o.component_mul(&o.map(|x| x * (1. - x))));
+                dg.rows_mut(3 * n, n)
+                    .copy_from(&dc_tilde.component_mul(&c_tilde.map(|x| 1. - x * x)));
+
+                dc.component_mul_assign(&f);
+            }
+            CellType::GRU => {
+                let (z, r, candidate) = (self.gate(g, 0), self.gate(g, 1), self.gate(g, 2));
+
+                let dz = dh.component_mul(&(h_prev - &candidate));
+                let d_candidate = dh
+                    .component_mul(&z.map(|z| 1. - z))
+                    .component_mul(&candidate.map(|x| 1. - x * x));
+                let dr = self
+                    .u
+                    .value
+                    .rows(2 * n, n)
+                    .tr_mul(&d_candidate)
+                    .component_mul(h_prev);
+
+                dg.rows_mut(0, n)
+                    .copy_from(&dz.component_mul(&z.map(|x| x * (1. - x))));
+                dg.rows_mut(n, n)
+                    .copy_from(&dr.component_mul(&r.map(|x| x * (1. - x))));
+                dg.rows_mut(2 * n, n).copy_from(&d_candidate);
+            }
+        }
+
+        dg
+    }
+}
+
+impl Layer for Recurrent {
+    fn forward(&mut self, data: &Matrix, _training: bool) -> Matrix {
+        let num_samples = data.ncols();
+
+        self.hs = vec![Matrix::zeros(self.num_units, num_samples)];
+        self.cs = vec![Matrix::zeros(self.num_units, num_samples)];
+        self.gates.clear();
+
+        for t in 0..self.timesteps {
+            let x = data
+                .rows(t * self.num_features, self.num_features)
+                .clone_owned();
+            self.step(&x);
+        }
+
+        self.a = if self.options.return_sequences {
+            stack_sequence(&self.hs[1..])
+        } else {
+            self.hs.last().unwrap().clone()
+        };
+
+        self.a.clone()
+    }
+
+    fn backward(&mut self, delta: Matrix, input: &Matrix) -> Matrix {
+        let n = self.num_units;
+        let num_samples = delta.ncols();
+
+        let mut dx = Matrix::zeros(input.nrows(), num_samples);
+        let mut dw = Matrix::zeros(self.w.value.nrows(), self.w.value.ncols());
+        let mut du = Matrix::zeros(self.u.value.nrows(), self.u.value.ncols());
+        let mut db = Matrix::zeros(self.bias.value.nrows(), 1);
+
+        let mut dh = Matrix::zeros(n, num_samples);
+        let mut dc = Matrix::zeros(n, num_samples);
+
+        for t in (0..self.timesteps).rev() {
+            if let Some(steps) = self.options.bptt_steps {
+                if (self.timesteps - t - 1).is_multiple_of(steps) {
+                    dh.fill(0.);
+                    dc.fill(0.);
+                }
+            }
+
+            if self.options.return_sequences {
+                dh += delta.rows(t * n, n);
+            } else if t == self.timesteps - 1 {
+                dh += &delta;
+            }
+
+            let dg = self.backward_step(t, &dh, &mut dc);
+            let x = input.rows(t * self.num_features, self.num_features);
+            let h_prev = &self.hs[t];
+
+            dw += &dg * x.transpose();
+            db += dg.column_sum().reshape_generic(Dyn(dg.nrows()), Dyn(1));
+            dx.rows_mut(t * self.num_features, self.num_features)
+                .copy_from(&self.w.value.tr_mul(&dg));
+
+            let new_dh = match self.cell_type {
+                CellType::GRU => {
+                    let (z, r) = (self.gate(&self.gates[t], 0), self.gate(&self.gates[t], 1));
+                    let d_candidate = dg.rows(2 * n, n);
+                    let d_reset_h = self.u.value.rows(2 * n, n).tr_mul(&d_candidate);
+
+                    let mut du_zr = du.rows_mut(0, 2 * n);
+                    du_zr += dg.rows(0, 2 * n) * h_prev.transpose();
+                    let mut du_candidate = du.rows_mut(2 * n, n);
+                    du_candidate += d_candidate * r.component_mul(h_prev).transpose();
+
+                    dh.component_mul(&z)
+                        + d_reset_h.component_mul(&r)
+                        + self.u.value.rows(0, 2 * n).tr_mul(&dg.rows(0, 2 * n))
+                }
+                _ => {
+                    du += &dg * h_prev.transpose();
+                    self.u.value.tr_mul(&dg)
+                }
+            };
+            dh = new_dh;
+        }
+
+        if !self.w.options.frozen {
+            self.w.accumulate(&dw);
+            self.u.accumulate(&du);
+            self.bias.accumulate(&db);
+        }
+
+        dx
+    }
+
+    fn output(&self) -> &Matrix {
+        &self.a
+    }
+
+    fn num_outputs(&self) -> usize {
+        self.a.nrows()
+    }
+
+    fn output_shape(&self) -> Shape {
+        if self.options.return_sequences {
+            Shape::sequence(self.timesteps, self.num_units)
+        } else {
+            Shape::flat(self.num_units)
+        }
+    }
+
+    fn parameters(&self) -> Vec<&Parameter> {
+        vec![&self.w, &self.u, &self.bias]
+    }
+
+    fn parameters_mut(&mut self) -> Vec<&mut Parameter> {
+        vec![&mut self.w, &mut self.u, &mut self.bias]
+    }
+
+    fn name(&self) -> &str {
+        "RECURRENT"
+    }
+
+    fn save(&self) -> Vec<String> {
+        vec![
+            format!("{:?}", self.cell_type),
+            format!(
+                "{} {} {} {} {}",
+                self.timesteps,
+                self.num_features,
+                self.num_units,
+                self.options.return_sequences as usize,
+                self.options.bptt_steps.unwrap_or(0)
+            ),
+            matrix_to_string(&self.w.value),
+            matrix_to_string(&self.u.value),
+            matrix_to_string(&self.bias.value),
+        ]
+    }
+
+    fn replicate(&self) -> Box<dyn Layer> {
+        Box::new(Recurrent {
+            cell_type: self.cell_type,
+            options: self.options,
+            w: self.w.clone(),
+            u: self.u.clone(),
+            bias: self.bias.clone(),
+            timesteps: self.timesteps,
+            num_features: self.num_features,
+            num_units: self.num_units,
+            a: Matrix::zeros(self.a.nrows(), 0),
+            hs: vec![],
+            cs: vec![],
+            gates: vec![],
+        })
+    }
+}
+
+fn sigmoid(x: f32) -> f32 {
+    1. / (1. + (-x).exp())
+}
+
+fn random_weights(rows: usize, cols: usize, num_units: usize) -> Matrix {
+    let distr = Normal::new(0., (1. / (cols + num_units) as f32).sqrt()).unwrap();
+
+    let mut rng = thread_rng();
+    Matrix::from_fn(rows, cols, |_, _| distr.sample(&mut rng))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        activation::ActivationType,
+        gradient_check::{fill_deterministic, gradient_check},
+        layers::{CellType, Layer, Recurrent, RecurrentOptions, Shape},
+        nn::NNBuilder,
+        utils::unstack_sequence,
+        Matrix,
+    };
+
+    #[test]
+    fn test_recurrent_gradients() {
+        let x = Matrix::from_fn(12, 3, |i, j| ((i * 5 + j * 3) % 7) as f32 / 7. - 0.5);
+        let y = Matrix::from_fn(2, 3, |i, j| ((i + j) % 2) as f32);
+
+        for cell_type in [CellType::SimpleRNN, CellType::LSTM, CellType::GRU] {
+            let sequences = RecurrentOptions {
+                return_sequences: true,
+                ..Default::default()
+            };
+
+            let mut nn = NNBuilder::with_input_shape(Shape::sequence(4, 3))
+                .add_recurrent(cell_type, 4, sequences)
+                .add_recurrent(cell_type, 3, Default::default())
+                .add_layer(2, ActivationType::Sigmoid)
+                .build();
+
+            fill_deterministic(&mut nn);
+
+            // rounding over the timesteps dominates the differences below this
+            // eps, the errors grow instead of shrinking with it
+            for error in gradient_check(&nn, &x, std::slice::from_ref(&y), 3e-2) {
+                assert!(error < 5e-2, "{cell_type:?}: relative error {error}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_truncated_bptt() {
+        let shape = Shape::sequence(3, 2);
+        let x = Matrix::from_fn(shape.len(), 2, |i, j| (i + j) as f32 / 4. - 0.5);
+
+        let input_gradients = |bptt_steps| {
+            let options = RecurrentOptions {
+                bptt_steps,
+                ..Default::default()
+            };
+            let mut layer = Recurrent::new(CellType::SimpleRNN, shape, 2, options);
+
+            let out = layer.forward(&x, true);
+            let dx = layer.backward(Matrix::from_element(out.nrows(), out.ncols(), 1.), &x);
+            unstack_sequence(&dx, 3)
+        };
+
+        // only the last timestep is in the last chunk of one step
+        let truncated = input_gradients(Some(1));
+        assert_eq!(truncated[0].amax(), 0.);
+        assert_eq!(truncated[1].amax(), 0.);
+        assert!(truncated[2].amax() > 0.);
+
+        let full = input_gradients(None);
+        assert!(full.iter().all(|dx| dx.amax() > 0.));
+    }
+}
@@ -1,13 +1,18 @@
 use nalgebra::{DMatrix, Dyn};
 
 pub mod activation;
+pub mod data;
+pub mod gradient_check;
+pub mod graph;
+pub mod layers;
+mod linalg;
+pub mod loss;
 pub mod nn;
 pub mod optimizers;
+pub mod parameter;
 pub mod storage;
 pub mod utils;
 
-mod layer;
-
 pub type Matrix = DMatrix<f32>;
 
 pub(crate) unsafe fn empty_like(shape: (usize, usize)) -> Matrix {
@@ -0,0 +1,114 @@
+use crate::Matrix;
+
+/// `op(a) * op(b)`, where `op` transposes its matrix if the flag next to it
+/// is set. Goes through `cblas_sgemm` with the `blas` feature
+pub(crate) fn matmul(a: &Matrix, transpose_a: bool, b: &Matrix, transpose_b: bool) -> Matrix {
+    let nrows = if transpose_a { a.ncols() } else { a.nrows() };
+    let ncols = if transpose_b { b.nrows() } else { b.ncols() };
+
+    let mut c = Matrix::zeros(nrows, ncols);
+    gemm(&mut c, a, transpose_a, b, transpose_b);
+    c
+}
+
+/// `c = op(a) * op(b)`, `c` must already have the right shape and is never
+/// read, so it may be uninitialized
+#[cfg(not(feature = "blas"))]
+pub(crate) fn gemm(c: &mut Matrix, a: &Matrix, transpose_a: bool, b: &Matrix, transpose_b: bool) {
+    match (transpose_a, transpose_b) {
+        (false, false) => a.mul_to(b, c),
+        (true, false) => a.tr_mul_to(b, c),
+        (false, true) => c.copy_from(&(a * b.transpose())),
+        (true, true) => c.copy_from(&(a.transpose() * b.transpose())),
+    }
+}
+
+#[cfg(feature = "blas")]
+pub(crate) fn gemm(c: &mut Matrix, a: &Matrix, transpose_a: bool, b: &Matrix, transpose_b: bool) {
+    let (m, n) = c.shape();
+    let k = if transpose_a { a.nrows() } else { a.ncols() };
+    assert_eq!(
+        (if transpose_a { a.ncols() } else { a.nrows() }, k),
+        (m, if transpose_b { b.ncols() } else { b.nrows() }),
+        "matrix product with incompatible shapes"
+    );
+    assert_eq!(n, if transpose_b { b.nrows() } else { b.ncols() });
+
+    let op = |transpose| {
+        if transpose {
+            cblas::TRANS
+        } else {
+            cblas::NO_TRANS
+        }
+    };
+    // the leading dimension may not be zero, even for empty matrices
+    let ld = |m: &Matrix| m.nrows().max(1) as i32;
+
+    unsafe {
+        cblas::cblas_sgemm(
+            cblas::COL_MAJOR,
+            op(transpose_a),
+            op(transpose_b),
+            m as i32,
+            n as i32,
+            k as i32,
+            1.,
+            a.as_ptr(),
+            ld(a),
+            b.as_ptr(),
+            ld(b),
+            0.,
+            c.as_mut_ptr(),
+            ld(c),
+        );
+    }
+}
+
+/// The part of the CBLAS interface that is used, nalgebra stores matrices
+/// column major
+#[cfg(feature = "blas")]
+mod cblas {
+    pub const COL_MAJOR: i32 = 102;
+    pub const NO_TRANS: i32 = 111;
+    pub const TRANS: i32 = 112;
+
+    #[cfg_attr(feature = "openblas", link(name = "openblas"))]
+    extern "C" {
+        #[allow(clippy::too_many_arguments)]
+        pub fn cblas_sgemm(
+            layout: i32,
+            transa: i32,
+            transb: i32,
+            m: i32,
+            n: i32,
+            k: i32,
+            alpha: f32,
+            a: *const f32,
+            lda: i32,
+            b: *const f32,
+            ldb: i32,
+            beta: f32,
+            c: *mut f32,
+            ldc: i32,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{linalg::matmul, Matrix};
+
+    #[test]
+    fn test_matmul() {
+        let a = Matrix::from_fn(3, 2, |i, j| (i * 2 + j) as f32 - 2.);
+        let b = Matrix::from_fn(3, 4, |i, j| (i + j * 3) as f32 / 4.);
+
+        assert_eq!(matmul(&a, true, &b, false), a.transpose() * &b);
+        assert_eq!(
+            matmul(&b, true, &a, false),
+            matmul(&b.transpose(), false, &a, false)
+        );
+        assert_eq!(matmul(&a, false, &a, true), &a * a.transpose());
+        assert_eq!(matmul(&a, true, &a.transpose(), true), a.transpose() * &a);
+    }
+}
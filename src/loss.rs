@@ -0,0 +1,154 @@
+use std::str::FromStr;
+
+use crate::Matrix;
+
+/// Keeps the logarithms of the cross entropy finite
+const EPSILON: f32 = 1e-7;
+
+/// How the outputs of a head are compared with its labels
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Loss {
+    /// `|predicted - label|^2 / 2`, so its derivative is `predicted - label`
+    #[default]
+    MeanSquared,
+    /// Binary cross entropy of every output, which should be a probability,
+    /// eg. the output of a sigmoid
+    CrossEntropy,
+}
+
+impl Loss {
+    /// Summed over all samples of the batch
+    pub fn loss(&self, predicted: &Matrix, label: &Matrix) -> f32 {
+        predicted
+            .iter()
+            .zip(label.iter())
+            .map(|(&p, &y)| self.element_loss(p, y))
+            .sum()
+    }
+
+    /// Like [Loss::loss], with the loss of every sample multiplied by its
+    /// weight, a single row with one column per sample
+    pub fn weighted_loss(&self, predicted: &Matrix, label: &Matrix, weights: &Matrix) -> f32 {
+        predicted
+            .column_iter()
+            .zip(label.column_iter())
+            .zip(weights.iter())
+            .map(|((p, y), w)| {
+                w * p
+                    .iter()
+                    .zip(y.iter())
+                    .map(|(&p, &y)| self.element_loss(p, y))
+                    .sum::<f32>()
+            })
+            .sum()
+    }
+
+    pub(crate) fn element_loss(&self, p: f32, y: f32) -> f32 {
+        match self {
+            Loss::MeanSquared => 0.5 * (p - y).powi(2),
+            Loss::CrossEntropy => {
+                let p = p.clamp(EPSILON, 1. - EPSILON);
+                -(y * p.ln() + (1. - y) * (1. - p).ln())
+            }
+        }
+    }
+
+    /// Derivative of the loss with respect to the predicted outputs
+    pub fn delta(&self, predicted: &Matrix, label: &Matrix) -> Matrix {
+        match self {
+            Loss::MeanSquared => predicted - label,
+            Loss::CrossEntropy => predicted.zip_map(label, |p, y| {
+                let p = p.clamp(EPSILON, 1. - EPSILON);
+                (p - y) / (p * (1. - p))
+            }),
+        }
+    }
+}
+
+/// Weights that make the samples of some classes count more than others,
+/// eg. for imbalanced datasets. The class of a one-hot label is its largest
+/// row, that of a single row label its value
+#[derive(Clone, Debug, PartialEq)]
+pub enum ClassWeights {
+    /// inversely proportional to how often every class occurs, so all
+    /// classes count the same in total
+    Balanced,
+    /// the weight of every class, indexed by class
+    Custom(Vec<f32>),
+}
+
+impl ClassWeights {
+    /// The weight of every sample, as a single row
+    pub fn sample_weights(&self, labels: &Matrix) -> Matrix {
+        let classes = labels
+            .column_iter()
+            .map(|y| match y.nrows() {
+                1 => y[0].round().max(0.) as usize,
+                _ => y.argmax().0,
+            })
+            .collect::<Vec<_>>();
+
+        let weights = match self {
+            ClassWeights::Balanced => {
+                let mut counts = vec![0; classes.iter().max().map_or(0, |c| c + 1)];
+                for &c in &classes {
+                    counts[c] += 1;
+                }
+                let num_classes = counts.iter().filter(|&&n| n > 0).count();
+
+                counts
+                    .iter()
+                    .map(|&n| classes.len() as f32 / (num_classes * n.max(1)) as f32)
+                    .collect()
+            }
+            ClassWeights::Custom(weights) => {
+                let num_classes = classes.iter().max().map_or(0, |c| c + 1);
+                assert!(
+                    weights.len() >= num_classes,
+                    "{num_classes} classes but only {} custom weights",
+                    weights.len()
+                );
+
+                weights.clone()
+            }
+        };
+
+        Matrix::from_iterator(1, classes.len(), classes.iter().map(|&c| weights[c]))
+    }
+}
+
+impl FromStr for Loss {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "MeanSquared" => Ok(Loss::MeanSquared),
+            "CrossEntropy" => Ok(Loss::CrossEntropy),
+            _ => Err(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::loss::{ClassWeights, Loss};
+    use nalgebra::dmatrix;
+
+    #[test]
+    fn test_class_weights() {
+        let labels = dmatrix![0., 0., 0., 1.];
+        let weights = ClassWeights::Balanced.sample_weights(&labels);
+        assert_eq!(weights, dmatrix![2. / 3., 2. / 3., 2. / 3., 2.]);
+
+        let one_hot = dmatrix![1., 0.; 0., 1.; 0., 0.];
+        let weights = ClassWeights::Custom(vec![1., 3., 5.]).sample_weights(&one_hot);
+        assert_eq!(weights, dmatrix![1., 3.]);
+
+        let predicted = dmatrix![0.5, 1.];
+        let label = dmatrix![0., 0.];
+        assert_eq!(
+            Loss::MeanSquared.weighted_loss(&predicted, &label, &weights),
+            0.125 + 1.5
+        );
+    }
+}
@@ -1,85 +1,413 @@
-use std::time::{Duration, Instant};
+use std::{
+    borrow::Cow,
+    ops::Range,
+    thread,
+    time::{Duration, Instant},
+};
 
 use crate::{
     activation::ActivationType,
-    layer::Layer,
-    optimizers::{Optimizer, OptimizerType},
+    data::{Batch, DataLoader, Dataset, MatrixDataset, Scaler},
+    graph::GraphBuilder,
+    layers::{
+        AvgPool2D, BatchNorm, CellType, Conv2D, Dense, Dropout, Embedding, Flatten, Layer,
+        LayerNorm, MaxPool2D, MultiHeadAttention, Recurrent, RecurrentOptions, Shape,
+    },
+    loss::Loss,
+    optimizers::{default_optimizer::DefaultOptimizer, Optimizer, OptimizerType},
+    utils::stack_sequence,
     Matrix,
 };
 
 pub struct NN {
-    pub(crate) layers: Vec<Layer>,
+    pub(crate) layers: Vec<Box<dyn Layer>>,
+    /// the nodes every layer reads from, node 0 is the input of the network
+    /// and node `i + 1` the output of layer `i`. Nodes only read from earlier
+    /// nodes
+    pub(crate) inputs: Vec<Vec<usize>>,
+    /// the nodes the network is trained to predict, by default only the
+    /// last layer
+    pub(crate) heads: Vec<Head>,
     pub(crate) options: NNOptions,
     pub(crate) optimizer: Box<dyn Optimizer>,
+    /// number of optimizer updates so far, kept across calls to [NN::train]
+    pub(crate) step: usize,
+    /// applied to the inputs before the first layer
+    pub(crate) scaler: Option<Box<dyn Scaler>>,
 
     test_accuracy: f32,
 }
 
 impl NN {
     pub(crate) fn new(
-        layers: Vec<Layer>,
+        layers: Vec<Box<dyn Layer>>,
+        inputs: Vec<Vec<usize>>,
         options: NNOptions,
         optimizer: Box<dyn Optimizer>,
     ) -> Self {
+        assert_eq!(layers.len(), inputs.len());
+        let heads = vec![Head::new(layers.len(), Loss::default(), 1.)];
+
         Self {
             layers,
+            inputs,
+            heads,
             options,
             optimizer,
+            step: 0,
+            scaler: None,
             test_accuracy: 0.,
         }
     }
 
+    /// Predict the outputs of the first head for `data`, with dropout disabled
     pub fn feed_forward(&mut self, data: &Matrix) -> Matrix {
-        let mut data = data.clone_owned();
-        for layer in &mut self.layers {
-            data = layer.step(&data);
+        let data = self.scale(data);
+        self.predict(&data, &[self.heads[0].node]).swap_remove(0)
+    }
+
+    /// Predict the outputs of every head for `data`, in the order they were
+    /// added to the [GraphBuilder](crate::graph::GraphBuilder)
+    pub fn feed_forward_heads(&mut self, data: &Matrix) -> Vec<Matrix> {
+        let data = self.scale(data);
+        let nodes = self.heads.iter().map(|head| head.node).collect::<Vec<_>>();
+        self.predict(&data, &nodes)
+    }
+
+    /// Predict the outputs for a sequence with one matrix per timestep, for
+    /// networks built on [Shape::sequence]
+    pub fn feed_forward_sequence(&mut self, sequence: &[Matrix]) -> Matrix {
+        self.feed_forward(&stack_sequence(sequence))
+    }
+
+    /// Scale the inputs of the network from now on, the scaler should be
+    /// fitted on the training data and is saved with the network. Networks
+    /// with a custom scaler are read back with [storage::read_with](crate::storage::read_with)
+    pub fn set_scaler<S: Scaler + 'static>(&mut self, scaler: S) {
+        self.scaler = Some(Box::new(scaler));
+    }
+
+    pub fn scaler(&self) -> Option<&dyn Scaler> {
+        self.scaler.as_deref()
+    }
+
+    pub(crate) fn scale<'a>(&self, data: &'a Matrix) -> Cow<'a, Matrix> {
+        match &self.scaler {
+            Some(scaler) => Cow::Owned(scaler.transform(data)),
+            None => Cow::Borrowed(data),
+        }
+    }
+
+    /// The outputs of `nodes` for `data`, split over the threads
+    fn predict(&mut self, data: &Matrix, nodes: &[usize]) -> Vec<Matrix> {
+        let shards = shards(data.ncols(), self.options.num_threads);
+        if shards.len() < 2 {
+            self.forward(data, false);
+            return self.node_outputs(data, nodes);
+        }
+
+        let (outputs, _) = self.parallel(&shards, |nn, shard| {
+            let data = columns(data, &shard);
+            nn.forward(&data, false);
+            nn.node_outputs(&data, nodes)
+        });
+
+        (0..nodes.len())
+            .map(|i| {
+                let mut joined = Matrix::zeros(outputs[0][i].nrows(), data.ncols());
+                for (shard, output) in shards.iter().zip(&outputs) {
+                    joined
+                        .columns_mut(shard.start, shard.len())
+                        .copy_from(&output[i]);
+                }
+                joined
+            })
+            .collect()
+    }
+
+    /// Run `f` on this network for the first shard of the columns and on a
+    /// new replica for every other shard, each on its own thread. Returns the
+    /// results and the replicas
+    fn parallel<R, F>(&mut self, shards: &[Range<usize>], f: F) -> (Vec<R>, Vec<NN>)
+    where
+        R: Send,
+        F: Fn(&mut NN, Range<usize>) -> R + Sync,
+    {
+        let mut replicas = shards[1..]
+            .iter()
+            .map(|shard| {
+                let mut replica = self.replica();
+                for layer in &mut replica.layers {
+                    layer.set_batch_offset(shard.start);
+                }
+                replica
+            })
+            .collect::<Vec<_>>();
+
+        let results = thread::scope(|scope| {
+            let f = &f;
+            let handles = replicas
+                .iter_mut()
+                .zip(&shards[1..])
+                .map(|(replica, shard)| scope.spawn(move || f(replica, shard.clone())))
+                .collect::<Vec<_>>();
+
+            let mut results = vec![f(self, shards[0].clone())];
+            results.extend(handles.into_iter().map(|h| h.join().unwrap()));
+            results
+        });
+
+        (results, replicas)
+    }
+
+    /// A copy of the network with all of its layer state and heads, but
+    /// without gradients, the scaler or the optimizer. It runs on a single
+    /// thread
+    pub(crate) fn replica(&self) -> NN {
+        let mut layers = self
+            .layers
+            .iter()
+            .map(|l| l.replicate())
+            .collect::<Vec<_>>();
+        for layer in &mut layers {
+            for parameter in layer.parameters_mut() {
+                parameter.zero_gradient();
+            }
+        }
+
+        let mut replica = NN::new(
+            layers,
+            self.inputs.clone(),
+            Default::default(),
+            DefaultOptimizer::boxed(),
+        );
+        replica.heads = self.heads.clone();
+
+        replica
+    }
+
+    /// Dropout is only applied when `training` is set
+    pub(crate) fn forward(&mut self, data: &Matrix, training: bool) -> Matrix {
+        let mut output = data.clone_owned();
+
+        for i in 0..self.layers.len() {
+            let (left, right) = self.layers.split_at_mut(i);
+            let input = gather(data, left, &self.inputs[i]);
+
+            output = right[0].forward(&input, training);
+        }
+
+        output
+    }
+
+    /// Stop updating the weights of a layer, it still passes delta on
+    pub fn freeze(&mut self, layer_idx: usize) {
+        for parameter in self.layers[layer_idx].parameters_mut() {
+            parameter.options.frozen = true;
+        }
+    }
+
+    pub fn unfreeze(&mut self, layer_idx: usize) {
+        for parameter in self.layers[layer_idx].parameters_mut() {
+            parameter.options.frozen = false;
+        }
+    }
+
+    /// Remove the output layer, eg. to replace the head of a loaded network.
+    /// Heads that read from it move to the layer before. Does nothing if the
+    /// network has no layers
+    pub fn pop_layer(&mut self) {
+        let n = self.layers.len();
+        let Some(layer) = self.layers.pop() else {
+            return;
+        };
+        self.inputs.pop();
+
+        if let Some(from) = layer.parameters().iter().map(|p| p.index).min() {
+            self.optimizer.remove_variables(from);
+        }
+
+        for head in &mut self.heads {
+            head.node = head.node.min(n - 1);
+        }
+    }
+
+    /// Append a new dense layer on top of the current output layer
+    pub fn add_layer(&mut self, num_neurons: usize, activation_type: ActivationType) {
+        let num_inputs = self
+            .layers
+            .last()
+            .expect("cannot infer the input size of a network without layers")
+            .num_outputs();
+
+        self.push_layer(Dense::new(num_inputs, num_neurons, activation_type));
+    }
+
+    /// Append any layer on top of the current output layer
+    pub fn push_layer<L: Layer + 'static>(&mut self, mut layer: L) {
+        layer.register(self.optimizer.as_mut());
+
+        let n = self.layers.len();
+        self.inputs.push(vec![n]);
+        self.layers.push(Box::new(layer));
+
+        for head in self.heads.iter_mut().filter(|head| head.node == n) {
+            head.node = n + 1;
         }
+    }
 
-        data.clone()
+    pub fn layers(&self) -> &[Box<dyn Layer>] {
+        &self.layers
     }
 
-    fn back_propagate(
+    pub fn set_options(&mut self, options: NNOptions) {
+        self.options = options;
+    }
+
+    /// Outputs of the heads after a forward pass
+    pub(crate) fn head_outputs(&self, x: &Matrix) -> Vec<Matrix> {
+        let nodes = self.heads.iter().map(|head| head.node).collect::<Vec<_>>();
+        self.node_outputs(x, &nodes)
+    }
+
+    fn node_outputs(&self, x: &Matrix, nodes: &[usize]) -> Vec<Matrix> {
+        nodes
+            .iter()
+            .map(|&node| node_output(x, &self.layers, node).clone_owned())
+            .collect()
+    }
+
+    /// Backpropagate the weighted loss of every head, `weights` weighs the
+    /// samples
+    pub(crate) fn back_propagate_heads(
         &mut self,
         x: &Matrix,
-        label: &Matrix,
-        predicted: &Matrix,
-        learning_rate: f32,
-        step: usize,
+        labels: &[Matrix],
+        predicted: &[Matrix],
+        weights: Option<&Matrix>,
     ) {
-        let cost = predicted - label;
-        let mut delta = cost;
+        let mut deltas: Vec<Option<Matrix>> = vec![None; self.layers.len() + 1];
 
+        for ((head, label), predicted) in self.heads.iter().zip(labels).zip(predicted) {
+            let mut delta = head.weight * head.loss.delta(predicted, label);
+            if let Some(weights) = weights {
+                for (mut col, w) in delta.column_iter_mut().zip(weights.iter()) {
+                    col *= *w;
+                }
+            }
+
+            match &mut deltas[head.node] {
+                Some(d) => *d += delta,
+                None => deltas[head.node] = Some(delta),
+            }
+        }
+
+        self.back_propagate_deltas(x, deltas);
+    }
+
+    /// `deltas` holds the derivative of the loss with respect to the output
+    /// of every node, or None if the loss does not depend on it
+    fn back_propagate_deltas(&mut self, x: &Matrix, mut deltas: Vec<Option<Matrix>>) {
         let n = self.layers.len();
 
         for i in (0..n).rev() {
-            let (layer, prev_a) = if i == 0 {
-                (&mut self.layers[i], x)
-            } else {
-                let (left, right) = self.layers.split_at_mut(i);
-                (&mut right[0], &left.last().unwrap().a)
+            // layers the output does not depend on
+            let Some(delta) = deltas[i + 1].take() else {
+                continue;
+            };
+
+            let (left, right) = self.layers.split_at_mut(i);
+            let dx = {
+                let input = gather(x, left, &self.inputs[i]);
+                right[0].backward(delta, &input)
             };
 
-            let optimizer = &mut self.optimizer;
-            delta = layer.back_propagate(
-                delta,
-                prev_a,
+            let mut offset = 0;
+            for &node in &self.inputs[i] {
+                let rows = node_output(x, left, node).nrows();
+                let d = dx.rows(offset, rows);
+                offset += rows;
+
+                match &mut deltas[node] {
+                    Some(delta) => *delta += d,
+                    None => deltas[node] = Some(d.clone_owned()),
+                }
+            }
+        }
+    }
+
+    fn apply_gradients(&mut self, learning_rate: f32) {
+        for layer in &mut self.layers {
+            layer.apply_gradients(
                 learning_rate,
                 self.options.weight_decay,
-                optimizer,
-                step,
+                self.optimizer.as_mut(),
+                self.step,
             );
         }
+
+        self.step += 1;
     }
 
     pub fn train(&mut self, x_train: &Matrix, y_train: &Matrix, x_test: &Matrix, y_test: &Matrix) {
-        let num_samples = x_train.ncols();
-        let batch_size = self.options.batch_size;
+        self.train_heads(
+            x_train,
+            std::slice::from_ref(y_train),
+            x_test,
+            std::slice::from_ref(y_test),
+        );
+    }
+
+    /// Train with the loss and gradient of every sample multiplied by its
+    /// weight, a single row with one column per sample. See
+    /// [ClassWeights](crate::loss::ClassWeights) to weigh classes
+    pub fn train_weighted(
+        &mut self,
+        x_train: &Matrix,
+        y_train: &Matrix,
+        weights: &Matrix,
+        x_test: &Matrix,
+        y_test: &Matrix,
+    ) {
+        let y_train = std::slice::from_ref(y_train);
+        let loader = DataLoader::new(
+            MatrixDataset::new(x_train, y_train).weights(weights),
+            self.options.batch_size,
+        );
+        self.fit(
+            &loader,
+            &MatrixDataset::new(x_test, std::slice::from_ref(y_test)),
+        );
+    }
+
+    /// Train a network with several heads, with one label matrix per head.
+    /// The loss is the weighted sum of the losses of all heads, the test
+    /// accuracy is that of the first head
+    pub fn train_heads(
+        &mut self,
+        x_train: &Matrix,
+        y_train: &[Matrix],
+        x_test: &Matrix,
+        y_test: &[Matrix],
+    ) {
+        let loader = DataLoader::new(
+            MatrixDataset::new(x_train, y_train),
+            self.options.batch_size,
+        );
+        self.fit(&loader, &MatrixDataset::new(x_test, y_test));
+    }
+
+    /// Train on batches from `loader`, which decides the batch size instead of
+    /// [NNOptions::batch_size]. `test` is only used when [NNOptions::test] is set
+    pub fn fit<D: Dataset, T: Dataset>(&mut self, loader: &DataLoader<D>, test: &T) {
+        let num_samples = loader.dataset().len();
+        let accumulate_steps = self.options.accumulate_steps;
+        let num_batches = loader.num_batches();
         let mut learning_rate = self.options.learning_rate;
 
-        assert!(num_samples % batch_size == 0);
+        assert!(accumulate_steps > 0);
 
         let start = Instant::now();
-        let mut step = 0;
         let mut best_loss = f32::MAX;
         let mut epochs_waited = 0;
 
@@ -93,44 +421,45 @@ impl NN {
                 }
             }
 
-            for i in (0..num_samples).step_by(batch_size) {
-                let batch_x = x_train.columns_range(i..(i + batch_size).min(num_samples));
-                let batch_y = y_train.columns_range(i..(i + batch_size).min(num_samples));
+            let mut batch = 0;
+            let mut seen = 0;
 
-                let predicted = self.feed_forward(&batch_x.into());
+            loader.for_each_batch(|Batch { x, y, weights }| {
+                assert_eq!(y.len(), self.heads.len(), "one label matrix per head");
 
-                self.back_propagate(
-                    &batch_x.into(),
-                    &batch_y.into(),
-                    &predicted,
-                    learning_rate,
-                    step,
-                );
+                let x = self.scale(&x).into_owned();
+                current_loss += self.train_batch(&x, &y, weights.as_ref());
 
-                current_loss += (predicted - batch_y).norm_squared();
-                step += 1;
+                // the last few batches of an epoch are applied even if there
+                // are fewer than accumulate_steps of them
+                if (batch + 1) % accumulate_steps == 0 || batch + 1 == num_batches {
+                    self.apply_gradients(learning_rate);
+                }
+
+                batch += 1;
+                seen += x.ncols();
 
                 if self.options.log_batches {
                     self.log(
                         epoch,
-                        i,
+                        seen,
                         num_samples,
                         start,
-                        current_loss / i as f32,
+                        current_loss / seen as f32,
                         learning_rate,
                     );
                 }
-            }
+            });
 
             current_loss /= num_samples as f32;
-            if self.options.weight_decay != 0. {
-                for layer in &self.layers {
-                    current_loss += self.options.weight_decay * layer.weights.norm_squared();
+            for layer in &self.layers {
+                for parameter in layer.parameters() {
+                    current_loss += parameter.decay_loss(self.options.weight_decay);
                 }
             }
 
             if self.options.test {
-                self.test_accuracy = self.test(x_test, y_test);
+                self.test_accuracy = self.test(test, loader.batch_size());
             }
             if self.options.log_interval.is_some_and(|x| epoch % x == 0) {
                 self.log(
@@ -164,6 +493,42 @@ impl NN {
         }
     }
 
+    /// Add the gradients of a batch to the parameters and return its loss.
+    /// The batch is split over the threads and the gradients of all shards
+    /// are summed, as they would have been on a single thread
+    fn train_batch(&mut self, x: &Matrix, y: &[Matrix], weights: Option<&Matrix>) -> f32 {
+        let train = |nn: &mut NN, x: &Matrix, y: &[Matrix], weights: Option<&Matrix>| {
+            nn.forward(x, true);
+            let predicted = nn.head_outputs(x);
+
+            nn.back_propagate_heads(x, y, &predicted, weights);
+            nn.heads_loss(&predicted, y, weights)
+        };
+
+        // the whole batch is needed to normalize over it
+        let shards = shards(x.ncols(), self.options.num_threads);
+        if shards.len() < 2 || self.layers.iter().any(|l| l.mixes_samples()) {
+            return train(self, x, y, weights);
+        }
+
+        let (losses, replicas) = self.parallel(&shards, |nn, shard| {
+            let y = y.iter().map(|y| columns(y, &shard)).collect::<Vec<_>>();
+            let weights = weights.map(|w| columns(w, &shard));
+
+            train(nn, &columns(x, &shard), &y, weights.as_ref())
+        });
+
+        for replica in &replicas {
+            for (layer, copy) in self.layers.iter_mut().zip(&replica.layers) {
+                for (p, q) in layer.parameters_mut().into_iter().zip(copy.parameters()) {
+                    p.merge(q);
+                }
+            }
+        }
+
+        losses.iter().sum()
+    }
+
     fn log(
         &mut self,
         epoch: usize,
@@ -192,26 +557,86 @@ impl NN {
         }
     }
 
-    fn test(&mut self, x_test: &Matrix, y_test: &Matrix) -> f32 {
-        let predicted = self.feed_forward(x_test);
+    /// The weighted sum of the losses of all heads
+    fn heads_loss(&self, predicted: &[Matrix], labels: &[Matrix], weights: Option<&Matrix>) -> f32 {
+        self.heads
+            .iter()
+            .zip(predicted.iter().zip(labels))
+            .map(|(head, (p, y))| {
+                let loss = match weights {
+                    Some(weights) => head.loss.weighted_loss(p, y, weights),
+                    None => head.loss.loss(p, y),
+                };
+                head.weight * loss
+            })
+            .sum()
+    }
+
+    /// The loss of every head per sample, without weighting
+    pub fn evaluate(&mut self, x: &Matrix, labels: &[Matrix]) -> Vec<f32> {
+        assert_eq!(labels.len(), self.heads.len(), "one label matrix per head");
 
+        let predicted = self.feed_forward_heads(x);
+
+        self.heads
+            .iter()
+            .zip(predicted.iter().zip(labels))
+            .map(|(head, (p, y))| head.loss.loss(p, y) / x.ncols() as f32)
+            .collect()
+    }
+
+    /// Fraction of the samples for which the largest output of the first head
+    /// matches the largest label
+    pub fn accuracy(&mut self, x: &Matrix, y: &Matrix) -> f32 {
+        let batch_size = self.options.batch_size;
+        self.test(&MatrixDataset::new(x, std::slice::from_ref(y)), batch_size)
+    }
+
+    /// Accuracy of the first head, evaluated `batch_size` samples at a time
+    fn test<T: Dataset>(&mut self, test: &T, batch_size: usize) -> f32 {
+        let indices = (0..test.len()).collect::<Vec<_>>();
         let mut num_correct = 0;
 
-        for (p, y) in predicted.column_iter().zip(y_test.column_iter()) {
-            let p_max = p.max();
-            let y_max = y.max();
+        for chunk in indices.chunks(batch_size) {
+            let Batch { x, y, .. } = test.get(chunk);
+            let predicted = self.feed_forward_heads(&x).swap_remove(0);
+
+            for (p, y) in predicted.column_iter().zip(y[0].column_iter()) {
+                let p_max = p.max();
+                let y_max = y.max();
 
-            let p_val = p.iter().position(|&x| x == p_max).unwrap();
-            let y_val = y.iter().position(|&x| x == y_max).unwrap();
+                let p_val = p.iter().position(|&x| x == p_max).unwrap();
+                let y_val = y.iter().position(|&x| x == y_max).unwrap();
 
-            num_correct += (p_val == y_val) as i32;
+                num_correct += (p_val == y_val) as i32;
+            }
         }
 
-        num_correct as f32 / x_test.ncols() as f32
+        num_correct as f32 / test.len() as f32
+    }
+}
+
+/// An output of the network, compared to its own labels while training
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Head {
+    pub(crate) node: usize,
+    pub(crate) loss: Loss,
+    /// the loss of this head is multiplied by its weight
+    pub(crate) weight: f32,
+}
+
+impl Head {
+    pub(crate) fn new(node: usize, loss: Loss, weight: f32) -> Self {
+        assert!(node > 0, "the input of the network cannot be a head");
+
+        Head { node, loss, weight }
     }
 }
 
 pub enum StopCondition {
+    /// the average loss per sample of an epoch, including weight decay. The
+    /// mean squared error is halved, `|predicted - label|^2 / 2`, so a
+    /// threshold meant for the full squared error has to be halved as well
     Loss(f32),
     Epoch(usize),
     Time(Duration),
@@ -236,6 +661,9 @@ pub struct NNOptions {
     pub log_batches: bool,
     pub test: bool,
     pub batch_size: usize,
+    /// sum the gradients of this many batches before updating the weights,
+    /// which behaves like a batch of size batch_size * accumulate_steps
+    pub accumulate_steps: usize,
     pub learning_rate: f32,
     /// multiply the lr by this factor is the loss does not improve for [patience] epochs
     pub learning_rate_factor: f32,
@@ -245,12 +673,18 @@ pub struct NNOptions {
     pub warmup_time: Option<usize>,
     pub stop_condition: StopCondition,
     pub weight_decay: f32,
+    /// split every batch, and the inputs of every prediction, over this many
+    /// threads. The gradients of all parts are summed before the update, so
+    /// training matches a single thread. Networks with batch norm are
+    /// trained on a single thread, as it normalizes over the whole batch
+    pub num_threads: usize,
 }
 
 impl Default for NNOptions {
     fn default() -> Self {
         NNOptions {
             batch_size: 1,
+            accumulate_steps: 1,
             learning_rate: 0.001,
             learning_rate_factor: 0.75,
             patience: None,
@@ -260,22 +694,54 @@ impl Default for NNOptions {
             warmup_time: None,
             stop_condition: StopCondition::Epoch(200),
             weight_decay: 0.0001,
+            num_threads: 1,
+        }
+    }
+}
+
+/// Overrides for the parameters of a single layer, set through [NNBuilder::layer_options]
+#[derive(Clone, Copy)]
+pub struct LayerOptions {
+    /// the layer's learning rate is the network's learning rate times this factor
+    pub learning_rate_multiplier: f32,
+    /// replaces [NNOptions::weight_decay] for this layer, biases are never decayed
+    pub weight_decay: Option<f32>,
+    /// a frozen layer still passes delta on to the previous layer, but its
+    /// weights and bias are never updated
+    pub frozen: bool,
+}
+
+impl Default for LayerOptions {
+    fn default() -> Self {
+        LayerOptions {
+            learning_rate_multiplier: 1.,
+            weight_decay: None,
+            frozen: false,
         }
     }
 }
 
 #[derive(Default)]
 pub struct NNBuilder {
-    layers: Vec<Layer>,
-    num_inputs: usize,
+    layers: Vec<Box<dyn Layer>>,
+    input_shape: Shape,
+    /// shape of the output of the most recently added layer
+    shape: Shape,
+    loss: Loss,
     options: NNOptions,
-    optimizer_type: OptimizerType,
+    optimizer: Box<dyn Optimizer>,
 }
 
 impl NNBuilder {
     pub fn new(num_inputs: usize) -> Self {
+        NNBuilder::with_input_shape(Shape::flat(num_inputs))
+    }
+
+    /// Start from images, see [Shape] for how a sample has to be laid out
+    pub fn with_input_shape(shape: Shape) -> Self {
         NNBuilder {
-            num_inputs,
+            input_shape: shape,
+            shape,
             ..Default::default()
         }
     }
@@ -286,37 +752,500 @@ impl NNBuilder {
     }
 
     pub fn optimizer(mut self, optimizer_type: OptimizerType) -> Self {
-        self.optimizer_type = optimizer_type;
+        self.optimizer = optimizer_type.optimizer();
         self
     }
 
-    pub fn add_layer(mut self, num_neurons: usize, activation_type: ActivationType) -> Self {
-        let num_inputs = if let Some(layer) = self.layers.last() {
-            layer.bias.nrows()
-        } else {
-            self.num_inputs
-        };
+    /// Use an optimizer that is not one of the built-in [OptimizerType]s
+    pub fn with_optimizer(mut self, optimizer: Box<dyn Optimizer>) -> Self {
+        self.optimizer = optimizer;
+        self
+    }
 
-        self.layers.push(Layer::new(
-            num_inputs,
-            num_neurons,
-            activation_type.activation(),
-            self.options.batch_size,
-        ));
+    /// The loss the output is trained with, [Loss::MeanSquared] by default
+    pub fn loss(mut self, loss: Loss) -> Self {
+        self.loss = loss;
+        self
+    }
+
+    pub fn add_layer(self, num_neurons: usize, activation_type: ActivationType) -> Self {
+        let num_inputs = self.num_outputs();
+
+        self.push_layer(Dense::new(num_inputs, num_neurons, activation_type))
+    }
+
+    /// Normalize the outputs of the previous layer over every batch
+    pub fn add_batch_norm(self) -> Self {
+        let num_features = self.num_outputs();
+
+        self.push_elementwise(BatchNorm::new(num_features))
+    }
 
+    /// Normalize every sample of the previous layer's outputs on its own
+    pub fn add_layer_norm(self) -> Self {
+        let num_features = self.num_outputs();
+
+        self.push_elementwise(LayerNorm::new(num_features))
+    }
+
+    /// Add any layer, its input size must match [NNBuilder::num_outputs]
+    pub fn push_layer<L: Layer + 'static>(mut self, layer: L) -> Self {
+        self.shape = layer.output_shape();
+        self.layers.push(Box::new(layer));
         self
     }
 
-    pub fn build(mut self) -> NN {
-        let mut optimizer = self.optimizer_type.optimizer();
-        if let StopCondition::TestAccuracy(_) = self.options.stop_condition {
-            self.options.test = true;
+    /// Add a layer that works on every value separately, so the shape of
+    /// its input is kept
+    fn push_elementwise<L: Layer + 'static>(self, layer: L) -> Self {
+        let shape = self.shape;
+
+        let mut builder = self.push_layer(layer);
+        builder.shape = shape;
+        builder
+    }
+
+    /// Convolve the images produced by the previous layer with `filters`
+    /// kernels of `kernel_size` x `kernel_size`
+    pub fn add_conv2d(
+        self,
+        filters: usize,
+        kernel_size: usize,
+        stride: usize,
+        padding: usize,
+        activation_type: ActivationType,
+    ) -> Self {
+        let shape = self.shape;
+
+        self.push_layer(Conv2D::new(
+            shape,
+            filters,
+            kernel_size,
+            stride,
+            padding,
+            activation_type,
+        ))
+    }
+
+    pub fn add_max_pool2d(self, size: usize, stride: usize) -> Self {
+        let shape = self.shape;
+
+        self.push_layer(MaxPool2D::new(shape, size, stride))
+    }
+
+    pub fn add_avg_pool2d(self, size: usize, stride: usize) -> Self {
+        let shape = self.shape;
+
+        self.push_layer(AvgPool2D::new(shape, size, stride))
+    }
+
+    /// Look up a learned vector of size `dim` for every output of the
+    /// previous layer, which must be integers below `vocab_size`
+    pub fn add_embedding(self, vocab_size: usize, dim: usize) -> Self {
+        let num_inputs = self.num_outputs();
+
+        self.push_layer(Embedding::new(num_inputs, vocab_size, dim))
+    }
+
+    /// Run a recurrent layer over the sequence produced by the previous
+    /// layer. Panics unless its output has a single channel, as the input
+    /// shapes built with [Shape::sequence] and the outputs of embeddings and
+    /// of recurrent layers that return sequences do
+    pub fn add_recurrent(
+        self,
+        cell_type: CellType,
+        num_units: usize,
+        options: RecurrentOptions,
+    ) -> Self {
+        let shape = self.shape;
+
+        self.push_layer(Recurrent::new(cell_type, shape, num_units, options))
+    }
+
+    /// Let every timestep of the sequence produced by the previous layer
+    /// attend to the others, see [MultiHeadAttention]
+    pub fn add_attention(self, num_heads: usize, causal: bool) -> Self {
+        let shape = self.shape;
+
+        self.push_layer(MultiHeadAttention::new(shape, num_heads, causal))
+    }
+
+    /// Treat the images produced by the previous layer as plain vectors
+    pub fn add_flatten(self) -> Self {
+        let shape = self.shape;
+
+        self.push_layer(Flatten::new(shape))
+    }
+
+    /// Size of the output of the most recently added layer
+    pub fn num_outputs(&self) -> usize {
+        self.shape.len()
+    }
+
+    /// Randomly drop this fraction of the outputs of the most recently
+    /// added layer while training
+    pub fn dropout(self, rate: f32) -> Self {
+        let num_features = self.num_outputs();
+
+        self.push_elementwise(Dropout::new(num_features, rate))
+    }
+
+    /// Set the options of the most recently added layer
+    pub fn layer_options(mut self, options: LayerOptions) -> Self {
+        let layer = self
+            .layers
+            .last_mut()
+            .expect("add a layer before setting its options");
+
+        for parameter in layer.parameters_mut() {
+            parameter.options = options;
         }
 
-        for layer in &mut self.layers {
-            layer.init(&mut optimizer);
+        self
+    }
+
+    /// A graph where every layer reads from the one before it
+    pub fn build(self) -> NN {
+        let mut graph = GraphBuilder::with_input_shape(self.input_shape)
+            .options(self.options)
+            .with_optimizer(self.optimizer);
+
+        let mut node = graph.input();
+        for layer in self.layers {
+            node = graph.push(vec![node.0], layer);
         }
+        graph.output(node, self.loss, 1.);
+
+        graph.build()
+    }
+}
+
+/// Ranges of columns for every thread, fewer if there are not enough columns
+fn shards(num_columns: usize, num_threads: usize) -> Vec<Range<usize>> {
+    let size = num_columns.div_ceil(num_threads.max(1)).max(1);
+
+    (0..num_columns)
+        .step_by(size)
+        .map(|start| start..(start + size).min(num_columns))
+        .collect()
+}
+
+fn columns(m: &Matrix, range: &Range<usize>) -> Matrix {
+    m.columns(range.start, range.len()).clone_owned()
+}
+
+fn node_output<'a>(x: &'a Matrix, layers: &'a [Box<dyn Layer>], node: usize) -> &'a Matrix {
+    match node {
+        0 => x,
+        _ => layers[node - 1].output(),
+    }
+}
+
+/// The input of a layer reading from `nodes`, the outputs of multiple nodes
+/// are stacked on top of each other
+fn gather<'a>(x: &'a Matrix, layers: &'a [Box<dyn Layer>], nodes: &[usize]) -> Cow<'a, Matrix> {
+    if let [node] = nodes {
+        return Cow::Borrowed(node_output(x, layers, *node));
+    }
+
+    let outputs = nodes
+        .iter()
+        .map(|&node| node_output(x, layers, node))
+        .collect::<Vec<_>>();
+    let num_rows = outputs.iter().map(|m| m.nrows()).sum();
 
-        NN::new(self.layers, self.options, optimizer)
+    let mut stacked = Matrix::zeros(num_rows, x.ncols());
+    let mut offset = 0;
+    for m in outputs {
+        stacked.rows_mut(offset, m.nrows()).copy_from(m);
+        offset += m.nrows();
+    }
+
+    Cow::Owned(stacked)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        activation::ActivationType,
+        layers::Shape,
+        nn::{LayerOptions, NNBuilder, NNOptions, StopCondition},
+        optimizers::OptimizerType,
+        Matrix,
+    };
+    use nalgebra::dmatrix;
+
+    fn options(batch_size: usize, accumulate_steps: usize) -> NNOptions {
+        NNOptions {
+            log_interval: None,
+            log_batches: false,
+            test: false,
+            batch_size,
+            accumulate_steps,
+            learning_rate: 0.1,
+            stop_condition: StopCondition::Epoch(0),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_gradient_accumulation() {
+        let x = dmatrix![
+            0., 1., 1., 0.;
+            1., 0., 1., 0.;
+        ];
+        let y = dmatrix![
+            0., 0., 1., 1.;
+            1., 1., 0., 0.;
+        ];
+
+        let mut full = NNBuilder::new(2)
+            .options(options(4, 1))
+            .add_layer(3, ActivationType::Sigmoid)
+            .add_layer(2, ActivationType::Sigmoid)
+            .build();
+        let mut accumulated = NNBuilder::new(2)
+            .options(options(2, 2))
+            .add_layer(3, ActivationType::Sigmoid)
+            .add_layer(2, ActivationType::Sigmoid)
+            .build();
+
+        for (l1, l2) in full.layers.iter().zip(accumulated.layers.iter_mut()) {
+            for (p1, p2) in l1.parameters().iter().zip(l2.parameters_mut()) {
+                p2.value.copy_from(&p1.value);
+            }
+        }
+
+        full.train(&x, &y, &x, &y);
+        accumulated.train(&x, &y, &x, &y);
+
+        for (l1, l2) in full.layers.iter().zip(accumulated.layers.iter()) {
+            for (p1, p2) in l1.parameters().iter().zip(l2.parameters()) {
+                assert!((&p1.value - &p2.value).amax() < 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn test_partial_batch() {
+        let x = dmatrix![
+            0., 1., 1., 0.;
+            1., 0., 1., 0.;
+        ];
+        let y = dmatrix![
+            0., 0., 1., 1.;
+            1., 1., 0., 0.;
+        ];
+
+        let mut full = NNBuilder::new(2)
+            .options(options(4, 1))
+            .add_layer(2, ActivationType::Sigmoid)
+            .build();
+        // the second batch only holds the last sample
+        let mut partial = NNBuilder::new(2)
+            .options(options(3, 2))
+            .add_layer(2, ActivationType::Sigmoid)
+            .build();
+
+        for (p1, p2) in full.layers[0]
+            .parameters()
+            .iter()
+            .zip(partial.layers[0].parameters_mut())
+        {
+            p2.value.copy_from(&p1.value);
+        }
+
+        full.train(&x, &y, &x, &y);
+        partial.train(&x, &y, &x, &y);
+
+        for (p1, p2) in full.layers[0]
+            .parameters()
+            .iter()
+            .zip(partial.layers[0].parameters())
+        {
+            assert!((&p1.value - &p2.value).amax() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_sample_weights() {
+        let x = dmatrix![
+            0., 1., 1.;
+            1., 0., 1.;
+        ];
+        let y = dmatrix![
+            0., 1., 1.;
+            1., 0., 0.;
+        ];
+        let weights = dmatrix![1., 2., 1.];
+
+        // the same samples, with the second one twice
+        let x_duplicated = x.select_columns(&[0, 1, 1, 2]);
+        let y_duplicated = y.select_columns(&[0, 1, 1, 2]);
+
+        let mut weighted = NNBuilder::new(2)
+            .options(options(3, 1))
+            .add_layer(3, ActivationType::Sigmoid)
+            .add_layer(2, ActivationType::Sigmoid)
+            .build();
+        let mut duplicated = weighted.replica();
+        duplicated.set_options(options(4, 1));
+
+        weighted.train_weighted(&x, &y, &weights, &x, &y);
+        duplicated.train(&x_duplicated, &y_duplicated, &x_duplicated, &y_duplicated);
+
+        for (l1, l2) in weighted.layers.iter().zip(duplicated.layers.iter()) {
+            for (p1, p2) in l1.parameters().iter().zip(l2.parameters()) {
+                assert!((&p1.value - &p2.value).amax() < 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn test_parallel_training() {
+        let shape = Shape::new(1, 4, 4);
+        let x = Matrix::from_fn(shape.len(), 7, |i, j| ((i * 3 + j * 5) % 7) as f32 / 7.);
+        let y = Matrix::from_fn(2, 7, |i, j| ((i + j) % 2) as f32);
+
+        let options = |num_threads| NNOptions {
+            stop_condition: StopCondition::Epoch(1),
+            num_threads,
+            ..options(7, 1)
+        };
+
+        // batch norm is trained on a single thread, but the replicas that
+        // predict need its running statistics
+        for batch_norm in [false, true] {
+            let mut builder = NNBuilder::with_input_shape(shape)
+                .options(options(1))
+                .add_conv2d(2, 3, 1, 1, ActivationType::ReLu)
+                .add_flatten();
+            if batch_norm {
+                builder = builder.add_batch_norm();
+            }
+            let mut single = builder
+                .dropout(0.3)
+                .add_layer(2, ActivationType::Sigmoid)
+                .build();
+
+            let mut parallel = single.replica();
+            parallel.set_options(options(3));
+
+            single.train(&x, &y, &x, &y);
+            parallel.train(&x, &y, &x, &y);
+
+            for (l1, l2) in single.layers.iter().zip(parallel.layers.iter()) {
+                for (p1, p2) in l1.parameters().iter().zip(l2.parameters()) {
+                    assert!((&p1.value - &p2.value).amax() < 1e-5);
+                }
+            }
+            assert!((single.feed_forward(&x) - parallel.feed_forward(&x)).amax() < 1e-5);
+
+            // the activations of the last batch are not copied
+            let replica = single.replica();
+            assert!(replica.layers.iter().all(|l| l.output().ncols() == 0));
+        }
+    }
+
+    #[test]
+    fn test_layer_options() {
+        let x = dmatrix![
+            0., 1., 1., 0.;
+            1., 0., 1., 0.;
+        ];
+
+        let mut nn = NNBuilder::new(2)
+            .options(options(4, 1))
+            .add_layer(3, ActivationType::Sigmoid)
+            .layer_options(LayerOptions {
+                frozen: true,
+                ..Default::default()
+            })
+            .add_layer(2, ActivationType::Sigmoid)
+            .layer_options(LayerOptions {
+                weight_decay: Some(1.),
+                ..Default::default()
+            })
+            .build();
+
+        let frozen_weights = nn.layers[0].parameters()[0].value.clone();
+        let frozen_bias = nn.layers[0].parameters()[1].value.clone();
+        let weights = nn.layers[1].parameters()[0].value.clone();
+        let bias = nn.layers[1].parameters()[1].value.clone();
+
+        // labels equal to the prediction leave only the weight decay term
+        let y = nn.feed_forward(&x);
+        nn.train(&x, &y, &x, &y);
+
+        assert_eq!(nn.layers[0].parameters()[0].value, frozen_weights);
+        assert_eq!(nn.layers[0].parameters()[1].value, frozen_bias);
+        assert!((&nn.layers[1].parameters()[0].value - &weights * 0.9).amax() < 1e-6);
+        assert_eq!(nn.layers[1].parameters()[1].value, bias);
+        assert_eq!(nn.layers[1].parameters()[1].gradient, Matrix::zeros(2, 1));
+    }
+
+    #[test]
+    fn test_replace_head() {
+        let x = dmatrix![
+            0., 1., 1., 0.;
+            1., 0., 1., 0.;
+        ];
+        let y = dmatrix![
+            0., 0., 1., 1.;
+            1., 1., 0., 0.;
+            0., 1., 0., 1.;
+        ];
+
+        let mut nn = NNBuilder::new(2)
+            .optimizer(OptimizerType::Adam)
+            .add_layer(3, ActivationType::Sigmoid)
+            .add_layer(2, ActivationType::Sigmoid)
+            .build();
+        nn.set_options(options(2, 1));
+
+        nn.pop_layer();
+        assert_eq!(nn.optimizer.state().len(), 2 * 2);
+        nn.add_layer(3, ActivationType::Sigmoid);
+        nn.freeze(0);
+
+        let frozen_weights = nn.layers[0].parameters()[0].value.clone();
+        let head_weights = nn.layers[1].parameters()[0].value.clone();
+
+        nn.train(&x, &y, &x, &y);
+
+        assert_eq!(nn.feed_forward(&x).shape(), (3, 4));
+        assert_eq!(nn.layers[0].parameters()[0].value, frozen_weights);
+        assert_ne!(nn.layers[1].parameters()[0].value, head_weights);
+
+        nn.unfreeze(0);
+        nn.train(&x, &y, &x, &y);
+
+        assert_ne!(nn.layers[0].parameters()[0].value, frozen_weights);
+
+        for _ in 0..3 {
+            nn.pop_layer();
+        }
+        assert!(nn.layers.is_empty() && nn.optimizer.state().is_empty());
+    }
+
+    #[test]
+    fn test_dropout() {
+        let x = Matrix::from_element(4, 50, 0.5);
+
+        let mut nn = NNBuilder::new(4)
+            .add_layer(8, ActivationType::Sigmoid)
+            .dropout(0.5)
+            .build();
+
+        let expected = nn.feed_forward(&x);
+        assert_eq!(nn.feed_forward(&x), expected);
+
+        let dropped = nn.forward(&x, true);
+        let num_zeros = dropped.iter().filter(|&&a| a == 0.).count();
+
+        assert!(num_zeros > 0 && num_zeros < dropped.len());
+        for (&a, &e) in dropped.iter().zip(expected.iter()) {
+            assert!(a == 0. || (a - 2. * e).abs() < 1e-6);
+        }
     }
 }
@@ -11,6 +11,10 @@ pub struct AdamOptimizer {
 }
 
 impl Optimizer for AdamOptimizer {
+    fn name(&self) -> &str {
+        "adam"
+    }
+
     fn add_variables(&mut self, shape: (usize, usize)) -> usize {
         self.momentum.push(Matrix::zeros(shape.0, shape.1));
         self.velocity.push(Matrix::zeros(shape.0, shape.1));
@@ -18,6 +22,11 @@ impl Optimizer for AdamOptimizer {
         self.momentum.len() - 1
     }
 
+    fn remove_variables(&mut self, from: usize) {
+        self.momentum.truncate(from);
+        self.velocity.truncate(from);
+    }
+
     fn step(
         &mut self,
         learning_rate: f32,
@@ -57,4 +66,53 @@ impl Optimizer for AdamOptimizer {
 
         *variables -= (learning_rate / (1. - beta_1_power)) * m.component_div(&buffer);
     }
+
+    /// Lazy Adam, the moments of columns without a gradient are not decayed
+    fn sparse_step(
+        &mut self,
+        learning_rate: f32,
+        gradient: &Matrix,
+        step: usize,
+        index: usize,
+        variables: &mut Matrix,
+        columns: &[usize],
+    ) {
+        let beta_1 = 0.9f32;
+        let beta_2 = 0.999f32;
+        let epsilon = 1e-7f32;
+
+        let step = (step + 1) as i32;
+        let beta_1_power = beta_1.powi(step);
+        let beta_2_power = beta_2.powi(step);
+
+        let m = &mut self.momentum[index];
+        let v = &mut self.velocity[index];
+
+        for &c in columns {
+            for r in 0..gradient.nrows() {
+                let g = gradient[(r, c)];
+
+                m[(r, c)] += (1. - beta_1) * (g - m[(r, c)]);
+                v[(r, c)] += (1. - beta_2) * (g * g - v[(r, c)]);
+
+                let denominator = (v[(r, c)] / (1. - beta_2_power)).sqrt() + epsilon;
+                variables[(r, c)] -= learning_rate / (1. - beta_1_power) * m[(r, c)] / denominator;
+            }
+        }
+    }
+
+    fn state(&self) -> Vec<Matrix> {
+        self.momentum
+            .iter()
+            .chain(self.velocity.iter())
+            .cloned()
+            .collect()
+    }
+
+    fn load_state(&mut self, mut state: Vec<Matrix>) {
+        assert_eq!(state.len(), 2 * self.momentum.len());
+
+        self.velocity = state.split_off(self.momentum.len());
+        self.momentum = state;
+    }
 }
@@ -6,10 +6,16 @@ use super::Optimizer;
 pub struct DefaultOptimizer;
 
 impl Optimizer for DefaultOptimizer {
+    fn name(&self) -> &str {
+        "default"
+    }
+
     fn add_variables(&mut self, _shape: (usize, usize)) -> usize {
         0
     }
 
+    fn remove_variables(&mut self, _from: usize) {}
+
     fn step(
         &mut self,
         learning_rate: f32,
@@ -20,4 +26,19 @@ impl Optimizer for DefaultOptimizer {
     ) {
         *variables -= learning_rate * gradient;
     }
+
+    fn sparse_step(
+        &mut self,
+        learning_rate: f32,
+        gradient: &Matrix,
+        _step: usize,
+        _index: usize,
+        variables: &mut Matrix,
+        columns: &[usize],
+    ) {
+        for &c in columns {
+            let mut column = variables.column_mut(c);
+            column -= learning_rate * gradient.column(c);
+        }
+    }
 }
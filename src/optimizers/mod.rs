@@ -22,7 +22,7 @@ impl OptimizerType {
     }
 }
 
-pub trait Optimizer {
+pub trait Optimizer: Send {
     fn boxed() -> Box<Self>
     where
         Self: Default,
@@ -30,8 +30,22 @@ pub trait Optimizer {
         Box::new(Self::default())
     }
 
+    /// Written to storage, a saved state is only restored into an optimizer
+    /// with the same name
+    fn name(&self) -> &str {
+        std::any::type_name::<Self>()
+    }
+
+    /// Register a variable of the given shape and return the index that is
+    /// passed to [Optimizer::step] for it
     fn add_variables(&mut self, shape: (usize, usize)) -> usize;
 
+    /// Forget the variables from index `from` on, the last ones that were
+    /// registered, eg. when the output layer of a network is removed. Any
+    /// state kept for them must be dropped, it would otherwise be applied to
+    /// the variables of a layer added later
+    fn remove_variables(&mut self, from: usize);
+
     fn step(
         &mut self,
         learning_rate: f32,
@@ -40,6 +54,30 @@ pub trait Optimizer {
         index: usize,
         variables: &mut Matrix,
     );
+
+    /// Like [Optimizer::step], but the gradient is known to be zero outside
+    /// of `columns`. Optimizers that keep state per variable can override this
+    /// to leave the other columns and their state untouched, the default is a
+    /// regular dense step
+    fn sparse_step(
+        &mut self,
+        learning_rate: f32,
+        gradient: &Matrix,
+        step: usize,
+        index: usize,
+        variables: &mut Matrix,
+        _columns: &[usize],
+    ) {
+        self.step(learning_rate, gradient, step, index, variables);
+    }
+
+    /// Everything needed to resume training, in a form [Optimizer::load_state]
+    /// accepts after the same variables were registered again
+    fn state(&self) -> Vec<Matrix> {
+        vec![]
+    }
+
+    fn load_state(&mut self, _state: Vec<Matrix>) {}
 }
 
 impl Default for Box<dyn Optimizer> {
@@ -0,0 +1,144 @@
+use std::mem;
+
+use crate::{nn::LayerOptions, optimizers::Optimizer, Matrix};
+
+/// A trainable matrix, the gradient accumulated for it since the last update
+/// and the optimizer slot it was registered under
+#[derive(Clone)]
+pub struct Parameter {
+    pub value: Matrix,
+    pub gradient: Matrix,
+    pub options: LayerOptions,
+    /// whether weight decay applies, which is never the case for biases
+    decay: bool,
+
+    pub(crate) index: usize,
+    /// columns that may have a gradient since the last update, only kept
+    /// for parameters that are updated with [Parameter::apply_sparse]
+    touched: Vec<usize>,
+}
+
+impl Parameter {
+    /// Biases and normalization parameters are created with `decay` unset
+    pub fn new(value: Matrix, decay: bool) -> Self {
+        let (nrows, ncols) = value.shape();
+
+        Parameter {
+            value,
+            gradient: Matrix::zeros(nrows, ncols),
+            options: LayerOptions::default(),
+            decay,
+            index: 0,
+            touched: vec![],
+        }
+    }
+
+    pub fn register(&mut self, optimizer: &mut dyn Optimizer) {
+        self.index = optimizer.add_variables(self.value.shape());
+    }
+
+    pub fn accumulate(&mut self, gradient: &Matrix) {
+        self.gradient += gradient;
+    }
+
+    /// Mark a column to be updated by [Parameter::apply_sparse]
+    pub fn touch(&mut self, column: usize) {
+        self.touched.push(column);
+    }
+
+    /// Add the gradient and touched columns of a copy of this parameter
+    pub(crate) fn merge(&mut self, other: &Parameter) {
+        self.gradient += &other.gradient;
+        self.touched.extend_from_slice(&other.touched);
+    }
+
+    /// Hand the accumulated gradient to the optimizer and reset it, the
+    /// parameter's own options take precedence over the network wide ones
+    pub fn apply(
+        &mut self,
+        learning_rate: f32,
+        weight_decay: f32,
+        optimizer: &mut dyn Optimizer,
+        step: usize,
+    ) {
+        if self.options.frozen {
+            self.zero_gradient();
+            return;
+        }
+
+        let learning_rate = learning_rate * self.options.learning_rate_multiplier;
+        let weight_decay = self.options.weight_decay.unwrap_or(weight_decay);
+
+        if self.decay && weight_decay != 0. {
+            self.gradient += weight_decay * &self.value;
+        }
+
+        optimizer.step(
+            learning_rate,
+            &self.gradient,
+            step,
+            self.index,
+            &mut self.value,
+        );
+        self.zero_gradient();
+    }
+
+    /// Like [Parameter::apply] for a gradient that is zero outside of the
+    /// columns passed to [Parameter::touch], see [Optimizer::sparse_step]
+    pub fn apply_sparse(
+        &mut self,
+        learning_rate: f32,
+        weight_decay: f32,
+        optimizer: &mut dyn Optimizer,
+        step: usize,
+    ) {
+        if self.options.frozen {
+            self.zero_gradient();
+            return;
+        }
+
+        let mut columns = mem::take(&mut self.touched);
+        columns.sort_unstable();
+        columns.dedup();
+
+        let learning_rate = learning_rate * self.options.learning_rate_multiplier;
+        let weight_decay = self.options.weight_decay.unwrap_or(weight_decay);
+
+        if self.decay && weight_decay != 0. {
+            for &c in &columns {
+                let decay = weight_decay * self.value.column(c);
+                let mut gradient = self.gradient.column_mut(c);
+                gradient += decay;
+            }
+        }
+
+        optimizer.sparse_step(
+            learning_rate,
+            &self.gradient,
+            step,
+            self.index,
+            &mut self.value,
+            &columns,
+        );
+
+        for &c in &columns {
+            self.gradient.column_mut(c).fill(0.);
+        }
+    }
+
+    /// The weight decay term this parameter adds to the loss,
+    /// `weight_decay * |value|^2 / 2`, whose derivative [Parameter::apply]
+    /// adds to the gradient
+    pub fn decay_loss(&self, weight_decay: f32) -> f32 {
+        if !self.decay {
+            return 0.;
+        }
+
+        0.5 * self.options.weight_decay.unwrap_or(weight_decay) * self.value.norm_squared()
+    }
+
+    pub fn zero_gradient(&mut self) {
+        self.gradient.fill(0.);
+        self.touched.clear();
+    }
+}
@@ -1,82 +1,225 @@
 use crate::{
-    activation::ActivationType,
-    layer::Layer,
-    nn::NN,
+    data::{MinMaxScaler, RobustScaler, Scaler, StandardScaler},
+    layers::{
+        Add, AvgPool2D, BatchNorm, Concat, Conv2D, Dense, Dropout, Embedding, Flatten, Layer,
+        LayerNorm, MaxPool2D, MultiHeadAttention, Recurrent,
+    },
+    loss::Loss,
+    nn::{Head, NN},
     optimizers::{adam_optimizer::AdamOptimizer, default_optimizer::DefaultOptimizer, Optimizer},
     Matrix,
 };
 use std::{fs, path::Path, str::FromStr};
 
+/// Builds a layer from the lines its [Layer::save] returned
+pub type LayerLoader = fn(&[&str]) -> Box<dyn Layer>;
+
+/// Builds a scaler from the lines its [Scaler::save] returned
+pub type ScalerLoader = fn(&[&str]) -> Box<dyn Scaler>;
+
 pub fn write_to<P: AsRef<Path>>(path: P, nn: &NN) -> Result<(), std::io::Error> {
     fs::write(path, nn_to_string(nn))
 }
 
 pub fn read_from<P: AsRef<Path>>(path: P) -> Result<NN, std::io::Error> {
+    read_with(path, None, &[], &[])
+}
+
+/// Read a network that was trained with a custom optimizer, its state is
+/// restored if it was saved under the same [Optimizer::name]
+pub fn read_with_optimizer<P: AsRef<Path>>(
+    path: P,
+    optimizer: Box<dyn Optimizer>,
+) -> Result<NN, std::io::Error> {
+    read_with(path, Some(optimizer), &[], &[])
+}
+
+/// Read a network that may contain layers or a scaler defined outside of
+/// this crate, `loaders` maps their [Layer::name] to the function that
+/// rebuilds them and `scalers` does the same for [Scaler::name]
+pub fn read_with<P: AsRef<Path>>(
+    path: P,
+    optimizer: Option<Box<dyn Optimizer>>,
+    loaders: &[(&str, LayerLoader)],
+    scalers: &[(&str, ScalerLoader)],
+) -> Result<NN, std::io::Error> {
     let contents = fs::read_to_string(path)?;
 
-    Ok(nn_from_string(&contents))
+    Ok(nn_from_string(&contents, optimizer, loaders, scalers))
 }
 
 fn nn_to_string(nn: &NN) -> String {
     let mut contents = String::new();
 
-    for layer in &nn.layers {
-        contents.push_str("BEGIN:LAYER\n");
-        contents.push_str(&matrix_to_string(&layer.bias));
-        contents.push_str("\n");
-        contents.push_str(&matrix_to_string(&layer.weights));
-        contents.push_str("\n");
-        contents.push_str(&format!("{:?}", layer.activation.activation_type));
-        contents.push_str("\n");
-        contents.push_str("END:LAYER\n");
+    for (i, (layer, inputs)) in nn.layers.iter().zip(&nn.inputs).enumerate() {
+        // only written for layers that do not read from the previous one
+        if inputs != &[i] {
+            let nodes = inputs.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+            contents.push_str(&format!("INPUTS:{}\n", nodes.join(" ")));
+        }
+
+        contents.push_str(&format!("BEGIN:{}\n", layer.name()));
+        for line in layer.save() {
+            contents.push_str(&line);
+            contents.push('\n');
+        }
+        contents.push_str(&format!("END:{}\n", layer.name()));
+    }
+
+    for head in &nn.heads {
+        contents.push_str(&format!(
+            "OUTPUT:{} {:?} {}\n",
+            head.node, head.loss, head.weight
+        ));
+    }
+
+    if let Some(scaler) = &nn.scaler {
+        contents.push_str(&format!("BEGIN:SCALER:{}\n", scaler.name()));
+        for line in scaler.save() {
+            contents.push_str(&line);
+            contents.push('\n');
+        }
+        contents.push_str(&format!("END:SCALER:{}\n", scaler.name()));
     }
 
-    contents.push_str(&format!("OPTIMIZER:{}", "adam"));
+    contents.push_str(&format!("STEP:{}\n", nn.step));
+
+    contents.push_str("BEGIN:OPTIMIZER_STATE\n");
+    for m in nn.optimizer.state() {
+        contents.push_str(&matrix_to_string(&m));
+        contents.push('\n');
+    }
+    contents.push_str("END:OPTIMIZER_STATE\n");
+
+    contents.push_str(&format!("OPTIMIZER:{}", nn.optimizer.name()));
 
     contents
 }
 
-fn nn_from_string(string: &str) -> NN {
+fn nn_from_string(
+    string: &str,
+    optimizer: Option<Box<dyn Optimizer>>,
+    loaders: &[(&str, LayerLoader)],
+    scalers: &[(&str, ScalerLoader)],
+) -> NN {
     let mut lines = string.lines();
     let mut layers = vec![];
-    let mut optimizer: Box<dyn Optimizer> = DefaultOptimizer::boxed();
+    let mut inputs = vec![];
+    let mut next_inputs = None;
+    let mut heads = vec![];
+    let mut optimizer_name = "default";
+    let mut optimizer_state = vec![];
+    let mut step = 0;
+    let mut scaler = None;
 
     while let Some(line) = lines.next() {
-        if line.contains("BEGIN:LAYER") {
-            let bias = matrix_from_string(lines.next().unwrap());
-            let weights = matrix_from_string(lines.next().unwrap());
-            let activation_type = ActivationType::from_str(lines.next().unwrap());
-
-            let mut layer = Layer::new(
-                weights.ncols(),
-                weights.nrows(),
-                activation_type.unwrap().activation(),
-                1,
-            );
-            layer.weights = weights;
-            layer.bias = bias;
+        if line.contains("BEGIN:OPTIMIZER_STATE") {
+            for line in lines.by_ref() {
+                if line.contains("END:OPTIMIZER_STATE") {
+                    break;
+                }
+                optimizer_state.push(matrix_from_string(line));
+            }
+        } else if let Some(name) = line.trim().strip_prefix("BEGIN:SCALER:") {
+            let end = format!("END:SCALER:{name}");
+            let block = lines
+                .by_ref()
+                .take_while(|line| line.trim() != end)
+                .collect::<Vec<_>>();
 
-            layers.push(layer);
+            scaler = Some(load_scaler(name, &block, scalers));
+        } else if let Some(name) = line.trim().strip_prefix("BEGIN:") {
+            let end = format!("END:{name}");
+            let block = lines
+                .by_ref()
+                .take_while(|line| line.trim() != end)
+                .collect::<Vec<_>>();
 
-            assert!(lines.next().unwrap().contains("END:LAYER"));
-        } else if line.contains("OPTIMIZER:") {
-            optimizer = match &line[10..] {
-                "ADAM" => AdamOptimizer::boxed(),
-                _ => DefaultOptimizer::boxed(),
-            };
+            inputs.push(next_inputs.take().unwrap_or(vec![layers.len()]));
+            layers.push(load_layer(name, &block, loaders));
+        } else if let Some(nodes) = line.strip_prefix("INPUTS:") {
+            next_inputs = Some(nodes.split(' ').map(|x| x.parse().unwrap()).collect());
+        } else if let Some(head) = line.strip_prefix("OUTPUT:") {
+            let parts = head.split(' ').collect::<Vec<_>>();
+            heads.push(Head::new(
+                parts[0].parse().unwrap(),
+                Loss::from_str(parts[1]).unwrap(),
+                parts[2].parse().unwrap(),
+            ));
+        } else if let Some(value) = line.strip_prefix("STEP:") {
+            step = value.parse().unwrap();
+        } else if let Some(name) = line.strip_prefix("OPTIMIZER:") {
+            optimizer_name = name;
         }
     }
 
-    NN::new(layers, Default::default(), optimizer)
+    let mut optimizer = optimizer.unwrap_or_else(|| match optimizer_name {
+        "adam" | "ADAM" => AdamOptimizer::boxed(),
+        _ => DefaultOptimizer::boxed(),
+    });
+
+    for layer in &mut layers {
+        layer.register(optimizer.as_mut());
+    }
+    if optimizer.name() == optimizer_name && !optimizer_state.is_empty() {
+        optimizer.load_state(optimizer_state);
+    }
+
+    let mut nn = NN::new(layers, inputs, Default::default(), optimizer);
+    nn.step = step;
+    nn.scaler = scaler;
+    // networks saved before heads were written predict their last layer
+    if !heads.is_empty() {
+        nn.heads = heads;
+    }
+
+    nn
+}
+
+fn load_layer(name: &str, lines: &[&str], loaders: &[(&str, LayerLoader)]) -> Box<dyn Layer> {
+    if let Some((_, loader)) = loaders.iter().find(|(n, _)| *n == name) {
+        return loader(lines);
+    }
+
+    match name {
+        // dense layers used to be the only kind and were saved as LAYER
+        "DENSE" | "LAYER" => Box::new(Dense::load(lines)),
+        "BATCH_NORM" => Box::new(BatchNorm::load(lines)),
+        "LAYER_NORM" => Box::new(LayerNorm::load(lines)),
+        "DROPOUT" => Box::new(Dropout::load(lines)),
+        "CONV2D" => Box::new(Conv2D::load(lines)),
+        "MAX_POOL2D" => Box::new(MaxPool2D::load(lines)),
+        "AVG_POOL2D" => Box::new(AvgPool2D::load(lines)),
+        "FLATTEN" => Box::new(Flatten::load(lines)),
+        "RECURRENT" => Box::new(Recurrent::load(lines)),
+        "EMBEDDING" => Box::new(Embedding::load(lines)),
+        "ADD" => Box::new(Add::load(lines)),
+        "CONCAT" => Box::new(Concat::load(lines)),
+        "ATTENTION" => Box::new(MultiHeadAttention::load(lines)),
+        _ => panic!("no loader for layer {name}"),
+    }
+}
+
+fn load_scaler(name: &str, lines: &[&str], loaders: &[(&str, ScalerLoader)]) -> Box<dyn Scaler> {
+    if let Some((_, loader)) = loaders.iter().find(|(n, _)| *n == name) {
+        return loader(lines);
+    }
+
+    match name {
+        "STANDARD" => Box::new(StandardScaler::load(lines)),
+        "MIN_MAX" => Box::new(MinMaxScaler::load(lines)),
+        "ROBUST" => Box::new(RobustScaler::load(lines)),
+        _ => panic!("no loader for scaler {name}"),
+    }
 }
 
-fn matrix_to_string(m: &Matrix) -> String {
+pub fn matrix_to_string(m: &Matrix) -> String {
     let mut result = String::new();
 
     result.push_str(&m.nrows().to_string());
-    result.push_str(" ");
+    result.push(' ');
     result.push_str(&m.ncols().to_string());
-    result.push_str(" ");
+    result.push(' ');
 
     result.push_str(
         &m.as_slice()
@@ -89,7 +232,7 @@ fn matrix_to_string(m: &Matrix) -> String {
     result
 }
 
-fn matrix_from_string(s: &str) -> Matrix {
+pub fn matrix_from_string(s: &str) -> Matrix {
     let mut parts = s.split(' ');
     let rows = parts.next().unwrap().parse::<usize>().unwrap();
     let cols = parts.next().unwrap().parse::<usize>().unwrap();
@@ -102,23 +245,303 @@ fn matrix_from_string(s: &str) -> Matrix {
 mod tests {
     use crate::{
         activation::ActivationType,
-        nn::NNBuilder,
-        storage::{nn_from_string, nn_to_string},
+        data::{RobustScaler, Scaler},
+        graph::GraphBuilder,
+        layers::{CellType, Layer, RecurrentOptions, Shape},
+        loss::Loss,
+        nn::{NNBuilder, NNOptions, StopCondition},
+        optimizers::{Optimizer, OptimizerType},
+        parameter::Parameter,
+        storage::{nn_from_string, nn_to_string, read_with_optimizer, write_to},
+        Matrix,
     };
+    use nalgebra::dmatrix;
 
     #[test]
     fn test_nn_parser() {
+        let mut nn = NNBuilder::new(2)
+            .add_layer(2, ActivationType::Sigmoid)
+            .dropout(0.25)
+            .add_batch_norm()
+            .add_layer_norm()
+            .add_layer(1, ActivationType::Sigmoid)
+            .build();
+        nn.set_scaler(RobustScaler::fit(&dmatrix![1., 2., 4.; 0., 3., 3.]));
+        let image_nn = NNBuilder::with_input_shape(Shape::new(1, 6, 6))
+            .add_conv2d(2, 3, 1, 1, ActivationType::ReLu)
+            .add_max_pool2d(2, 2)
+            .add_avg_pool2d(2, 1)
+            .add_flatten()
+            .add_layer(1, ActivationType::Sigmoid)
+            .build();
+
+        let sequence_nn = NNBuilder::new(3)
+            .add_embedding(10, 2)
+            .add_attention(2, true)
+            .add_recurrent(
+                CellType::LSTM,
+                4,
+                RecurrentOptions {
+                    return_sequences: true,
+                    bptt_steps: Some(2),
+                },
+            )
+            .add_recurrent(CellType::GRU, 2, Default::default())
+            .build();
+
+        let mut graph = GraphBuilder::new(2);
+        let input = graph.input();
+        let hidden = graph.dense(input, 2, ActivationType::ReLu);
+        let residual = graph.add(&[input, hidden]);
+        let joined = graph.concat(&[residual, hidden]);
+        let class = graph.dense(joined, 1, ActivationType::Sigmoid);
+        graph.output(class, Loss::CrossEntropy, 2.);
+        graph.output(hidden, Loss::MeanSquared, 0.5);
+        let graph_nn = graph.build();
+
+        for nn in [nn, image_nn, sequence_nn, graph_nn] {
+            let parsed = nn_from_string(&nn_to_string(&nn), None, &[], &[]);
+
+            assert_eq!(nn.layers.len(), parsed.layers.len());
+            assert_eq!(nn.inputs, parsed.inputs);
+            assert_eq!(nn.heads, parsed.heads);
+            assert_eq!(
+                nn.scaler().map(|s| (s.name().to_string(), s.save())),
+                parsed.scaler().map(|s| (s.name().to_string(), s.save()))
+            );
+
+            for (l1, l2) in nn.layers.iter().zip(parsed.layers.iter()) {
+                assert_eq!(l1.name(), l2.name());
+                assert_eq!(l1.save(), l2.save());
+            }
+        }
+    }
+
+    /// multiplies its input by a learnable scalar
+    #[derive(Clone)]
+    struct Scale {
+        scale: Parameter,
+        a: Matrix,
+    }
+
+    impl Layer for Scale {
+        fn forward(&mut self, input: &Matrix, _training: bool) -> Matrix {
+            self.a = input * self.scale.value[0];
+            self.a.clone()
+        }
+
+        fn backward(&mut self, delta: Matrix, input: &Matrix) -> Matrix {
+            self.scale
+                .accumulate(&Matrix::from_element(1, 1, delta.dot(input)));
+            delta * self.scale.value[0]
+        }
+
+        fn output(&self) -> &Matrix {
+            &self.a
+        }
+
+        fn num_outputs(&self) -> usize {
+            self.a.nrows()
+        }
+
+        fn parameters(&self) -> Vec<&Parameter> {
+            vec![&self.scale]
+        }
+
+        fn parameters_mut(&mut self) -> Vec<&mut Parameter> {
+            vec![&mut self.scale]
+        }
+
+        fn name(&self) -> &str {
+            "SCALE"
+        }
+
+        fn save(&self) -> Vec<String> {
+            vec![self.a.nrows().to_string(), self.scale.value[0].to_string()]
+        }
+
+        fn replicate(&self) -> Box<dyn Layer> {
+            Box::new(self.clone())
+        }
+    }
+
+    fn load_scale(lines: &[&str]) -> Box<dyn Layer> {
+        Box::new(Scale {
+            scale: Parameter::new(Matrix::from_element(1, 1, lines[1].parse().unwrap()), false),
+            a: Matrix::zeros(lines[0].parse().unwrap(), 0),
+        })
+    }
+
+    #[test]
+    fn test_custom_layer() {
         let nn = NNBuilder::new(2)
+            .add_layer(3, ActivationType::Sigmoid)
+            .push_layer(Scale {
+                scale: Parameter::new(Matrix::from_element(1, 1, 0.5), false),
+                a: Matrix::zeros(3, 0),
+            })
+            .add_layer(1, ActivationType::Sigmoid)
+            .build();
+
+        let mut parsed = nn_from_string(&nn_to_string(&nn), None, &[("SCALE", load_scale)], &[]);
+
+        assert_eq!(parsed.layers[1].name(), "SCALE");
+        assert_eq!(parsed.layers[1].save(), nn.layers[1].save());
+        assert_eq!(parsed.feed_forward(&Matrix::zeros(2, 4)).shape(), (1, 4));
+    }
+
+    /// scales every input by a fixed factor
+    struct Factor(f32);
+
+    impl Scaler for Factor {
+        fn transform(&self, x: &Matrix) -> Matrix {
+            x * self.0
+        }
+
+        fn inverse_transform(&self, x: &Matrix) -> Matrix {
+            x / self.0
+        }
+
+        fn name(&self) -> &str {
+            "FACTOR"
+        }
+
+        fn save(&self) -> Vec<String> {
+            vec![self.0.to_string()]
+        }
+    }
+
+    fn load_factor(lines: &[&str]) -> Box<dyn Scaler> {
+        Box::new(Factor(lines[0].parse().unwrap()))
+    }
+
+    #[test]
+    fn test_custom_scaler() {
+        let mut nn = NNBuilder::new(2)
+            .add_layer(1, ActivationType::Sigmoid)
+            .build();
+        nn.set_scaler(Factor(0.5));
+
+        let parsed = nn_from_string(&nn_to_string(&nn), None, &[], &[("FACTOR", load_factor)]);
+
+        let scaler = parsed.scaler().unwrap();
+        assert_eq!(scaler.name(), "FACTOR");
+        assert_eq!(scaler.save(), vec!["0.5"]);
+    }
+
+    #[test]
+    fn test_optimizer_state() {
+        let x = dmatrix![
+            0., 1., 1., 0.;
+            1., 0., 1., 0.;
+        ];
+        let y = dmatrix![
+            0., 0., 1., 1.;
+        ];
+        let options = NNOptions {
+            log_interval: None,
+            log_batches: false,
+            test: false,
+            batch_size: 2,
+            stop_condition: StopCondition::Epoch(2),
+            ..Default::default()
+        };
+
+        let mut nn = NNBuilder::new(2)
+            .options(options)
             .add_layer(2, ActivationType::Sigmoid)
             .add_layer(1, ActivationType::Sigmoid)
+            .optimizer(OptimizerType::Adam)
             .build();
-        let parsed = nn_from_string(&nn_to_string(&nn));
+        nn.train(&x, &y, &x, &y);
+
+        let parsed = nn_from_string(&nn_to_string(&nn), None, &[], &[]);
 
-        assert_eq!(nn.layers.len(), parsed.layers.len());
+        assert_eq!(parsed.optimizer.name(), "adam");
+        assert_eq!(parsed.step, 6);
+        assert_eq!(parsed.optimizer.state(), nn.optimizer.state());
+    }
+
+    /// gradient descent with momentum, keeps a velocity per variable
+    #[derive(Default)]
+    struct Momentum {
+        velocity: Vec<Matrix>,
+    }
+
+    impl Optimizer for Momentum {
+        fn name(&self) -> &str {
+            "momentum"
+        }
+
+        fn add_variables(&mut self, shape: (usize, usize)) -> usize {
+            self.velocity.push(Matrix::zeros(shape.0, shape.1));
+            self.velocity.len() - 1
+        }
 
-        for (l1, l2) in nn.layers.iter().zip(parsed.layers.iter()) {
-            assert_eq!(l1.bias, l2.bias);
-            assert_eq!(l1.weights, l2.weights);
+        fn remove_variables(&mut self, from: usize) {
+            self.velocity.truncate(from);
         }
+
+        fn step(
+            &mut self,
+            learning_rate: f32,
+            gradient: &Matrix,
+            _step: usize,
+            index: usize,
+            variables: &mut Matrix,
+        ) {
+            let v = &mut self.velocity[index];
+            *v = &*v * 0.9 + gradient * learning_rate;
+            *variables -= &*v;
+        }
+
+        fn state(&self) -> Vec<Matrix> {
+            self.velocity.clone()
+        }
+
+        fn load_state(&mut self, state: Vec<Matrix>) {
+            self.velocity = state;
+        }
+    }
+
+    #[test]
+    fn test_custom_optimizer() {
+        let x = dmatrix![
+            0., 1., 1., 0.;
+            1., 0., 1., 0.;
+        ];
+        let y = dmatrix![
+            0., 0., 1., 1.;
+        ];
+        let options = NNOptions {
+            log_interval: None,
+            log_batches: false,
+            test: false,
+            batch_size: 2,
+            stop_condition: StopCondition::Epoch(2),
+            ..Default::default()
+        };
+
+        let mut nn = NNBuilder::new(2)
+            .options(options)
+            .add_layer(2, ActivationType::Sigmoid)
+            .add_layer(1, ActivationType::Sigmoid)
+            .with_optimizer(Box::<Momentum>::default())
+            .build();
+        nn.train(&x, &y, &x, &y);
+        assert_eq!(nn.optimizer.name(), "momentum");
+        assert!(nn.optimizer.state().iter().any(|v| v.amax() > 0.));
+
+        let path = std::env::temp_dir().join("jabba-test-custom-optimizer.txt");
+        write_to(&path, &nn).unwrap();
+        let mut parsed = read_with_optimizer(&path, Box::<Momentum>::default()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(parsed.optimizer.name(), "momentum");
+        assert_eq!(parsed.optimizer.state(), nn.optimizer.state());
+
+        // the velocity of the removed layer must not be reused
+        parsed.pop_layer();
+        assert_eq!(parsed.optimizer.state(), nn.optimizer.state()[..2]);
     }
 }
@@ -10,28 +10,140 @@ pub fn shuffle_rows(m: &mut Matrix) {
     }
 }
 
+/// Turn class indices into columns with a one in the row of their class.
 /// One of m's axes should be of length one
 /// eg. m is either of shape (n, 1) or (1, n)
-pub fn one_hot(m: &Matrix) -> Matrix {
-    if m.ncols() == 1 {
-        let mut one_hot = Matrix::zeros(m.max() as usize + 1, m.nrows());
+pub fn one_hot(m: &Matrix, num_classes: usize) -> Matrix {
+    assert!(
+        m.nrows() == 1 || m.ncols() == 1,
+        "one_hot expects a single row or column of labels"
+    );
+    let mut one_hot = Matrix::zeros(num_classes, m.len());
 
-        one_hot
-            .row_iter_mut()
-            .enumerate()
-            .for_each(|(i, mut row)| row[m[(i, 0)] as usize] = 1.);
+    for (mut col, &label) in one_hot.column_iter_mut().zip(m.iter()) {
+        assert!(
+            label >= 0. && label.fract() == 0. && (label as usize) < num_classes,
+            "{label} is not a class index below {num_classes}"
+        );
+        col[label as usize] = 1.;
+    }
+
+    one_hot
+}
+
+/// The row of the largest value of every column, eg. the predicted class
+pub fn argmax(m: &Matrix) -> Vec<usize> {
+    m.column_iter().map(|col| col.argmax().0).collect()
+}
+
+/// Inverse of [one_hot], the class index of every column as a single row
+pub fn from_one_hot(m: &Matrix) -> Matrix {
+    let classes = argmax(m);
+
+    Matrix::from_iterator(1, classes.len(), classes.into_iter().map(|c| c as f32))
+}
+
+/// Move `smoothing` of the probability of every one-hot label evenly over
+/// all classes, so the network is not pushed towards extreme outputs
+pub fn smooth_labels(m: &Matrix, smoothing: f32) -> Matrix {
+    assert!((0. ..=1.).contains(&smoothing));
+    let uniform = smoothing / m.nrows() as f32;
+
+    m.map(|y| y * (1. - smoothing) + uniform)
+}
+
+/// Maps arbitrary labels, eg. the names of classes, to class indices and back.
+/// The classes are sorted, so the indices do not depend on the order of the
+/// labels the encoder was fitted on
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LabelEncoder {
+    classes: Vec<String>,
+}
+
+impl LabelEncoder {
+    pub fn fit<S: AsRef<str>>(labels: &[S]) -> Self {
+        let mut classes = labels
+            .iter()
+            .map(|label| label.as_ref().to_string())
+            .collect::<Vec<_>>();
+        classes.sort();
+        classes.dedup();
+
+        LabelEncoder { classes }
+    }
 
-        one_hot
-    } else {
-        let mut one_hot = Matrix::zeros(m.max() as usize + 1, m.ncols());
+    pub fn classes(&self) -> &[String] {
+        &self.classes
+    }
 
-        one_hot
-            .column_iter_mut()
-            .enumerate()
-            .for_each(|(i, mut col)| col[m[(0, i)] as usize] = 1.);
+    pub fn num_classes(&self) -> usize {
+        self.classes.len()
+    }
+
+    pub fn index(&self, label: &str) -> Option<usize> {
+        self.classes
+            .binary_search_by(|class| class.as_str().cmp(label))
+            .ok()
+    }
 
-        one_hot
+    /// The class index of every label as a single row, panics on labels the
+    /// encoder was not fitted on
+    pub fn transform<S: AsRef<str>>(&self, labels: &[S]) -> Matrix {
+        Matrix::from_iterator(
+            1,
+            labels.len(),
+            labels.iter().map(|label| {
+                let label = label.as_ref();
+                self.index(label)
+                    .unwrap_or_else(|| panic!("unknown label {label}")) as f32
+            }),
+        )
     }
+
+    /// Like [LabelEncoder::transform], one-hot encoded
+    pub fn one_hot<S: AsRef<str>>(&self, labels: &[S]) -> Matrix {
+        one_hot(&self.transform(labels), self.num_classes())
+    }
+
+    /// The labels of class indices, or of one-hot columns such as the
+    /// predictions of a network
+    pub fn inverse_transform(&self, m: &Matrix) -> Vec<String> {
+        let indices = if m.nrows() == 1 {
+            m.iter().map(|&i| i as usize).collect()
+        } else {
+            argmax(m)
+        };
+
+        indices
+            .into_iter()
+            .map(|i| self.classes[i].clone())
+            .collect()
+    }
+}
+
+/// Put the timesteps of a sequence below each other, so that every column
+/// holds a whole sample
+pub fn stack_sequence(sequence: &[Matrix]) -> Matrix {
+    let num_features = sequence[0].nrows();
+    let mut stacked = Matrix::zeros(sequence.len() * num_features, sequence[0].ncols());
+
+    for (t, step) in sequence.iter().enumerate() {
+        stacked
+            .rows_mut(t * num_features, num_features)
+            .copy_from(step);
+    }
+
+    stacked
+}
+
+/// Inverse of [stack_sequence], eg. to split the output of a recurrent
+/// layer that returns sequences into its timesteps
+pub fn unstack_sequence(stacked: &Matrix, timesteps: usize) -> Vec<Matrix> {
+    let num_features = stacked.nrows() / timesteps;
+
+    (0..timesteps)
+        .map(|t| stacked.rows(t * num_features, num_features).clone_owned())
+        .collect()
 }
 
 #[allow(unused)]
@@ -57,3 +169,37 @@ pub(crate) fn sqrt_to(m: &Matrix, buffer: &mut Matrix) {
         *x = y.sqrt();
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::{from_one_hot, one_hot, smooth_labels, LabelEncoder};
+    use nalgebra::dmatrix;
+
+    #[test]
+    fn test_label_encoding() {
+        // the highest class does not occur
+        let labels = dmatrix![0.; 2.; 1.; 2.];
+        let encoded = one_hot(&labels, 4);
+        assert_eq!(
+            encoded,
+            dmatrix![
+                1., 0., 0., 0.;
+                0., 0., 1., 0.;
+                0., 1., 0., 1.;
+                0., 0., 0., 0.;
+            ]
+        );
+        assert_eq!(from_one_hot(&encoded), labels.transpose());
+
+        let smoothed = smooth_labels(&encoded, 0.2);
+        assert_eq!(smoothed.column(0), dmatrix![0.85; 0.05; 0.05; 0.05]);
+
+        let encoder = LabelEncoder::fit(&["dog", "cat", "dog", "bird"]);
+        assert_eq!(encoder.classes(), ["bird", "cat", "dog"]);
+        assert_eq!(encoder.transform(&["cat", "bird"]), dmatrix![1., 0.]);
+        assert_eq!(
+            encoder.inverse_transform(&encoder.one_hot(&["dog", "cat"])),
+            ["dog", "cat"]
+        );
+    }
+}